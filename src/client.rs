@@ -1,6 +1,7 @@
 use cached::proc_macro::cached;
+use futures::future::{BoxFuture, Shared};
 use futures_lite::future::block_on;
-use futures_lite::{future::Boxed, FutureExt};
+use futures_lite::{future::Boxed, FutureExt, StreamExt};
 use hyper::client::HttpConnector;
 use hyper::{body, body::Buf, client, header, Body, Client, Method, Request, Response, Uri};
 use hyper_rustls::HttpsConnector;
@@ -10,9 +11,14 @@ use once_cell::sync::Lazy;
 use percent_encoding::{percent_encode, CONTROLS};
 use serde_json::Value;
 
-use std::{io, result::Result};
-use tokio::sync::RwLock;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Mutex, PoisonError};
+use std::{io, result::Result, sync::Arc};
+use tokio::sync::{OwnedSemaphorePermit, RwLock, Semaphore};
+use url::Url;
 
+use crate::config::get_setting;
 use crate::dbg_msg;
 use crate::oauth::{force_refresh_token, token_daemon, Oauth};
 use crate::server::RequestExt;
@@ -20,6 +26,15 @@ use crate::utils::format_url;
 
 const REDDIT_URL_BASE: &str = "https://oauth.reddit.com";
 
+// Used when REDLIB_PROXY_MAX_CONCURRENCY is unset or invalid.
+const DEFAULT_PROXY_MAX_CONCURRENCY: usize = 64;
+
+// Used when REDLIB_PROXY_CACHE_MAX_AGE is unset or invalid.
+const DEFAULT_PROXY_CACHE_MAX_AGE: u64 = 31_536_000;
+
+// Reddit's media hosts, whose files never change once uploaded.
+const IMMUTABLE_MEDIA_HOSTS: [&str; 3] = ["i.redd.it", "preview.redd.it", "v.redd.it"];
+
 pub static CLIENT: Lazy<Client<HttpsConnector<HttpConnector>>> = Lazy::new(|| {
 	let https = hyper_rustls::HttpsConnectorBuilder::new()
 		.with_native_roots()
@@ -30,6 +45,44 @@ pub static CLIENT: Lazy<Client<HttpsConnector<HttpConnector>>> = Lazy::new(|| {
 	client::Client::builder().build(https)
 });
 
+/// Limits the number of media proxy requests in flight at once across the
+/// whole process, so a burst of requests can't exhaust upstream sockets.
+static PROXY_PERMITS: Lazy<Arc<Semaphore>> = Lazy::new(|| {
+	let limit = get_setting("REDLIB_PROXY_MAX_CONCURRENCY")
+		.and_then(|val| val.parse::<usize>().ok())
+		.filter(|&limit| limit > 0)
+		.unwrap_or(DEFAULT_PROXY_MAX_CONCURRENCY);
+	Arc::new(Semaphore::new(limit))
+});
+
+/// The largest media file the proxy will relay, if the operator set one
+/// through REDLIB_PROXY_MAX_BYTES.
+static PROXY_MAX_BYTES: Lazy<Option<u64>> = Lazy::new(|| get_setting("REDLIB_PROXY_MAX_BYTES").and_then(|val| val.parse::<u64>().ok()).filter(|&max| max > 0));
+
+/// Whether the proxy asks Reddit's preview hosts for JPEG rather than WebP,
+/// set through REDLIB_PROXY_FORCE_JPEG.
+static PROXY_FORCE_JPEG: Lazy<bool> = Lazy::new(|| get_setting("REDLIB_PROXY_FORCE_JPEG").is_some_and(|val| val == "on"));
+
+/// Whether the proxy reports the EXIF orientation of JPEGs, set through
+/// REDLIB_PROXY_APPLY_EXIF_ROTATION.
+static PROXY_APPLY_EXIF_ROTATION: Lazy<bool> = Lazy::new(|| get_setting("REDLIB_PROXY_APPLY_EXIF_ROTATION").is_some_and(|val| val == "on"));
+
+/// How long browsers may cache media from Reddit's media hosts.
+static PROXY_CACHE_MAX_AGE: Lazy<u64> = Lazy::new(|| {
+	get_setting("REDLIB_PROXY_CACHE_MAX_AGE")
+		.and_then(|val| val.parse::<u64>().ok())
+		.unwrap_or(DEFAULT_PROXY_CACHE_MAX_AGE)
+});
+
+/// Whether identical Reddit fetches share one upstream request. On unless
+/// REDLIB_COALESCE_REQUESTS is "off".
+static COALESCE_REQUESTS: Lazy<bool> = Lazy::new(|| get_setting("REDLIB_COALESCE_REQUESTS").is_none_or(|val| val != "off"));
+
+type SharedFetch = Shared<BoxFuture<'static, Result<Value, String>>>;
+
+/// Reddit fetches currently in flight, keyed by path and quarantine opt-in.
+static IN_FLIGHT: Lazy<Mutex<HashMap<(String, bool), SharedFetch>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
 pub static OAUTH_CLIENT: Lazy<RwLock<Oauth>> = Lazy::new(|| {
 	let client = block_on(Oauth::new());
 	tokio::spawn(token_daemon());
@@ -96,6 +149,18 @@ pub async fn canonical_path(path: String) -> Result<Option<String>, String> {
 }
 
 pub async fn proxy(req: Request<Body>, format: &str) -> Result<Response<Body>, String> {
+	// Fail fast rather than queueing when every slot is already in use
+	let Some(permit) = proxy_permit(&PROXY_PERMITS) else {
+		return Ok(
+			Response::builder()
+				.status(503)
+				.header("content-type", "text/plain")
+				.header("Retry-After", "1")
+				.body("Too many concurrent media requests".into())
+				.unwrap_or_default(),
+		);
+	};
+
 	let mut url = format!("{format}?{}", req.uri().query().unwrap_or_default());
 
 	// For each parameter in request
@@ -104,7 +169,202 @@ pub async fn proxy(req: Request<Body>, format: &str) -> Result<Response<Body>, S
 		url = url.replace(&format!("{{{name}}}"), value);
 	}
 
-	stream(&url, &req).await
+	if *PROXY_FORCE_JPEG {
+		url = force_jpeg(&url);
+	}
+
+	let mut res = stream(&url, &req).await?;
+	if res.status().is_success() {
+		if let Ok(val) = header::HeaderValue::from_str(&cache_control(&url, *PROXY_CACHE_MAX_AGE)) {
+			res.headers_mut().insert(header::CACHE_CONTROL, val);
+		}
+	}
+	if let Some(max) = *PROXY_MAX_BYTES {
+		res = limit_size(res, max);
+	}
+	// GIFs and PNGs are relayed byte-for-byte, even from a URL forced to JPEG
+	if *PROXY_APPLY_EXIF_ROTATION && !is_transparent(&res) {
+		res = tag_orientation(res).await;
+	}
+	Ok(hold_permit(res, permit))
+}
+
+/// Picks the `Cache-Control` policy for media proxied from `url`. Files on
+/// Reddit's media hosts are content-addressed, so they can be cached for
+/// `max_age` seconds without revalidation; anything else must be revalidated.
+fn cache_control(url: &str, max_age: u64) -> String {
+	let host = url.parse::<Uri>().ok().and_then(|uri| uri.host().map(str::to_owned)).unwrap_or_default();
+
+	if IMMUTABLE_MEDIA_HOSTS.contains(&host.as_str()) {
+		format!("public, max-age={max_age}, immutable")
+	} else {
+		"no-cache".to_string()
+	}
+}
+
+/// Reports the EXIF orientation of a JPEG response in an `X-Image-Orientation`
+/// header, so clients that ignore EXIF can rotate the image themselves. Only
+/// the first chunk of the body is inspected, which is where the EXIF segment
+/// sits in practice; the body is otherwise streamed unchanged.
+async fn tag_orientation(res: Response<Body>) -> Response<Body> {
+	if !matches!(media_type(&res).as_str(), "image/jpeg" | "image/jpg") {
+		return res;
+	}
+
+	let (mut parts, mut body) = res.into_parts();
+	let Some(first) = body.next().await else {
+		return Response::from_parts(parts, Body::empty());
+	};
+	if let Some(orientation) = first.as_ref().ok().and_then(|chunk| exif_orientation(chunk)) {
+		parts.headers.insert("X-Image-Orientation", orientation.into());
+	}
+	Response::from_parts(parts, Body::wrap_stream(futures_lite::stream::once(first).chain(body)))
+}
+
+/// Reads the EXIF orientation (1-8) from the start of a JPEG, if it has one.
+fn exif_orientation(jpeg: &[u8]) -> Option<u16> {
+	if !jpeg.starts_with(&[0xFF, 0xD8]) {
+		return None;
+	}
+
+	// Walk the segments ahead of the image data, looking for the EXIF one
+	let mut pos = 2;
+	while jpeg.get(pos) == Some(&0xFF) {
+		let marker = *jpeg.get(pos + 1)?;
+		let len = usize::from(u16::from_be_bytes([*jpeg.get(pos + 2)?, *jpeg.get(pos + 3)?]));
+		// Image data starts at the start-of-scan marker
+		if marker == 0xDA {
+			return None;
+		}
+		let segment = jpeg.get(pos + 4..pos + 2 + len)?;
+		if marker == 0xE1 {
+			if let Some(tiff) = segment.strip_prefix(b"Exif\0\0") {
+				return tiff_orientation(tiff);
+			}
+		}
+		pos += 2 + len;
+	}
+	None
+}
+
+/// Reads the orientation tag from the first IFD of an EXIF TIFF block.
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+	let big_endian = match tiff.get(..2)? {
+		b"MM" => true,
+		b"II" => false,
+		_ => return None,
+	};
+	let u16_at = |at: usize| -> Option<u16> {
+		let bytes = [*tiff.get(at)?, *tiff.get(at + 1)?];
+		Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+	};
+	let u32_at = |at: usize| -> Option<u32> {
+		let bytes = [*tiff.get(at)?, *tiff.get(at + 1)?, *tiff.get(at + 2)?, *tiff.get(at + 3)?];
+		Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+	};
+
+	let ifd = usize::try_from(u32_at(4)?).ok()?;
+	let entries = usize::from(u16_at(ifd)?);
+	(0..entries)
+		.map(|i| ifd + 2 + i * 12)
+		.find(|&entry| u16_at(entry) == Some(0x0112))
+		.and_then(|entry| u16_at(entry + 8))
+		.filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// Caps a proxied response at `max` bytes. Responses whose `Content-Length`
+/// exceeds the cap are rejected with a 413 before anything is streamed;
+/// otherwise the body is counted as it streams and cut off once it goes over.
+fn limit_size(res: Response<Body>, max: u64) -> Response<Body> {
+	let declared = res
+		.headers()
+		.get(header::CONTENT_LENGTH)
+		.and_then(|len| len.to_str().ok())
+		.and_then(|len| len.parse::<u64>().ok());
+	if declared.is_some_and(|len| len > max) {
+		return Response::builder()
+			.status(413)
+			.header("content-type", "text/plain")
+			.body("Media exceeds the maximum proxied size".into())
+			.unwrap_or_default();
+	}
+
+	let (parts, body) = res.into_parts();
+	let mut streamed: u64 = 0;
+	let body = Body::wrap_stream(body.map(move |chunk| -> Result<body::Bytes, Box<dyn std::error::Error + Send + Sync>> {
+		let chunk = chunk?;
+		streamed += chunk.len() as u64;
+		if streamed > max {
+			Err(io::Error::other("media exceeds the maximum proxied size").into())
+		} else {
+			Ok(chunk)
+		}
+	}));
+	Response::from_parts(parts, body)
+}
+
+/// Whether an upstream response is a GIF or PNG, which may rely on
+/// transparency. Reddit often serves PNGs from `.jpg` preview URLs, so this
+/// goes by the response's `Content-Type` rather than the file extension.
+fn is_transparent(res: &Response<Body>) -> bool {
+	matches!(media_type(res).as_str(), "image/gif" | "image/png")
+}
+
+/// The media type of a response, lowercased and without parameters.
+fn media_type(res: &Response<Body>) -> String {
+	let kind = res.headers().get(header::CONTENT_TYPE).and_then(|kind| kind.to_str().ok()).unwrap_or_default();
+	kind.split(';').next().unwrap_or_default().trim().to_lowercase()
+}
+
+/// Asks Reddit's image preview hosts for JPEG rather than WebP by rewriting
+/// the `format` and `auto` query parameters. Other URLs, and GIF or PNG
+/// previews that may rely on transparency, are left untouched.
+fn force_jpeg(url: &str) -> String {
+	let Ok(mut parsed) = Url::parse(url) else {
+		return url.to_string();
+	};
+	if !matches!(parsed.host_str(), Some("preview.redd.it" | "external-preview.redd.it")) {
+		return url.to_string();
+	}
+	let path = parsed.path().to_lowercase();
+	if path.ends_with(".gif") || path.ends_with(".png") {
+		return url.to_string();
+	}
+
+	let pairs: Vec<(String, String)> = parsed
+		.query_pairs()
+		.filter(|(key, value)| !(key == "auto" && value == "webp"))
+		.map(|(key, value)| {
+			if key == "format" {
+				(key.into_owned(), "pjpg".to_string())
+			} else {
+				(key.into_owned(), value.into_owned())
+			}
+		})
+		.collect();
+	let mut query = parsed.query_pairs_mut();
+	query.clear().extend_pairs(&pairs);
+	if !pairs.iter().any(|(key, _)| key == "format") {
+		query.append_pair("format", "pjpg");
+	}
+	drop(query);
+	parsed.to_string()
+}
+
+/// Takes a slot from `permits`, or returns `None` if every slot is in use.
+fn proxy_permit(permits: &Arc<Semaphore>) -> Option<OwnedSemaphorePermit> {
+	permits.clone().try_acquire_owned().ok()
+}
+
+/// Ties `permit` to the lifetime of the response body, so the slot is only
+/// released once the media has been fully streamed to the client.
+fn hold_permit(res: Response<Body>, permit: OwnedSemaphorePermit) -> Response<Body> {
+	let (parts, body) = res.into_parts();
+	let body = Body::wrap_stream(body.map(move |chunk| {
+		let _ = &permit;
+		chunk
+	}));
+	Response::from_parts(parts, body)
 }
 
 async fn stream(url: &str, req: &Request<Body>) -> Result<Response<Body>, String> {
@@ -125,28 +385,29 @@ async fn stream(url: &str, req: &Request<Body>) -> Result<Response<Body>, String
 
 	let stream_request = builder.body(Body::empty()).map_err(|_| "Couldn't build empty body in stream".to_string())?;
 
-	client
-		.request(stream_request)
-		.await
-		.map(|mut res| {
-			let mut rm = |key: &str| res.headers_mut().remove(key);
-
-			rm("access-control-expose-headers");
-			rm("server");
-			rm("vary");
-			rm("etag");
-			rm("x-cdn");
-			rm("x-cdn-client-region");
-			rm("x-cdn-name");
-			rm("x-cdn-server-region");
-			rm("x-reddit-cdn");
-			rm("x-reddit-video-features");
-			rm("Nel");
-			rm("Report-To");
+	client.request(stream_request).await.map(relay).map_err(|e| e.to_string())
+}
 
-			res
-		})
-		.map_err(|e| e.to_string())
+/// Strips Reddit's CDN headers from an upstream media response. The body and
+/// `Content-Type` are relayed untouched, so GIFs and PNGs reach the client
+/// byte-for-byte with their transparency intact.
+fn relay(mut res: Response<Body>) -> Response<Body> {
+	let mut rm = |key: &str| res.headers_mut().remove(key);
+
+	rm("access-control-expose-headers");
+	rm("server");
+	rm("vary");
+	rm("etag");
+	rm("x-cdn");
+	rm("x-cdn-client-region");
+	rm("x-cdn-name");
+	rm("x-cdn-server-region");
+	rm("x-reddit-cdn");
+	rm("x-reddit-video-features");
+	rm("Nel");
+	rm("Report-To");
+
+	res
 }
 
 /// Makes a GET request to Reddit at `path`. By default, this will honor HTTP
@@ -233,7 +494,7 @@ fn request(method: &'static Method, path: String, redirect: bool, quarantine: bo
 									//
 									//     2. Percent-encode the path.
 									let new_path = percent_encode(val.as_bytes(), CONTROLS).to_string().trim_start_matches(REDDIT_URL_BASE).to_string();
-									format!("{new_path}{}raw_json=1", if new_path.contains('?') { "&" } else { "?" })
+									with_raw_json(&new_path)
 								})
 								.unwrap_or_default()
 								.to_string(),
@@ -305,6 +566,56 @@ fn request(method: &'static Method, path: String, redirect: bool, quarantine: bo
 // Make a request to a Reddit API and parse the JSON response
 #[cached(size = 100, time = 30, result = true)]
 pub async fn json(path: String, quarantine: bool) -> Result<Value, String> {
+	if !*COALESCE_REQUESTS {
+		return fetch_json(path, quarantine).await;
+	}
+	coalesce((path.clone(), quarantine), || fetch_json(path, quarantine)).await
+}
+
+/// Runs `fetch` unless an identical fetch is already in flight, in which case
+/// its result is awaited and shared instead. This keeps a cold cache from
+/// sending Reddit one request per visitor when a page gets popular.
+async fn coalesce<F, Fut>(key: (String, bool), fetch: F) -> Result<Value, String>
+where
+	F: FnOnce() -> Fut,
+	Fut: Future<Output = Result<Value, String>> + Send + 'static,
+{
+	let shared = {
+		let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(PoisonError::into_inner);
+		in_flight.entry(key.clone()).or_insert_with(|| futures::FutureExt::shared(fetch().boxed())).clone()
+	};
+
+	let result = shared.clone().await;
+
+	// Whoever finishes first retires the fetch so later calls start afresh
+	let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(PoisonError::into_inner);
+	if in_flight.get(&key).is_some_and(|current| current.ptr_eq(&shared)) {
+		in_flight.remove(&key);
+	}
+
+	result
+}
+
+/// Adds `raw_json=1` to an API path unless it's already there. Without it,
+/// Reddit HTML-escapes the strings in its responses, leaving media URLs with
+/// `&amp;` in their query that the proxy can't sign-check or fetch.
+fn with_raw_json(path: &str) -> String {
+	let query = path.split_once('?').map_or("", |(_, query)| query);
+	if query.split('&').any(|pair| pair.starts_with("raw_json=")) {
+		return path.to_string();
+	}
+	let separator = match query {
+		_ if !path.contains('?') => "?",
+		"" => "",
+		_ if query.ends_with('&') => "",
+		_ => "&",
+	};
+	format!("{path}{separator}raw_json=1")
+}
+
+async fn fetch_json(path: String, quarantine: bool) -> Result<Value, String> {
+	let path = with_raw_json(&path);
+
 	// Closure to quickly build errors
 	let err = |msg: &str, e: String| -> Result<Value, String> {
 		// eprintln!("{} - {}: {}", url, msg, e);
@@ -353,6 +664,144 @@ pub async fn json(path: String, quarantine: bool) -> Result<Value, String> {
 	}
 }
 
+#[test]
+fn test_proxy_permit_limit() {
+	let permits = Arc::new(Semaphore::new(2));
+
+	let first = proxy_permit(&permits).unwrap();
+	let _second = proxy_permit(&permits).unwrap();
+
+	// Every slot is taken, so the next request is rejected
+	assert!(proxy_permit(&permits).is_none());
+
+	// Finishing a request frees its slot again
+	drop(first);
+	assert!(proxy_permit(&permits).is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hold_permit_until_body_dropped() {
+	let permits = Arc::new(Semaphore::new(1));
+	let permit = proxy_permit(&permits).unwrap();
+
+	let res = hold_permit(Response::new(Body::from("media")), permit);
+	assert!(proxy_permit(&permits).is_none());
+
+	let body = body::to_bytes(res.into_body()).await.unwrap();
+	assert_eq!(body, "media");
+	assert!(proxy_permit(&permits).is_some());
+}
+
+#[test]
+fn test_force_jpeg_only_rewrites_previews() {
+	assert_eq!(
+		force_jpeg("https://preview.redd.it/abc.jpg?width=640&format=png8&auto=webp&s=123"),
+		"https://preview.redd.it/abc.jpg?width=640&format=pjpg&s=123"
+	);
+	assert_eq!(
+		force_jpeg("https://external-preview.redd.it/abc.jpeg?auto=webp&s=123"),
+		"https://external-preview.redd.it/abc.jpeg?s=123&format=pjpg"
+	);
+	let transparent = "https://preview.redd.it/abc.gif?format=png8&s=123";
+	assert_eq!(force_jpeg(transparent), transparent);
+	let image = "https://i.redd.it/abc.png?auto=webp";
+	assert_eq!(force_jpeg(image), image);
+	let video = "https://v.redd.it/abc/DASH_720.mp4?source=fallback";
+	assert_eq!(force_jpeg(video), video);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_relay_passes_png_through() {
+	let png: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0";
+	let upstream = Response::builder()
+		.header("content-type", "image/png")
+		.header("server", "snooserv")
+		.header("x-reddit-cdn", "fastly")
+		.body(Body::from(png))
+		.unwrap();
+
+	// Preview URLs often carry a .jpg extension while serving a PNG
+	let url = "https://preview.redd.it/abc.jpg?width=640&format=png8&auto=webp&s=123";
+	assert_eq!(force_jpeg(url), "https://preview.redd.it/abc.jpg?width=640&format=pjpg&s=123");
+	let res = relay(upstream);
+	assert!(is_transparent(&res));
+	assert_eq!(res.headers().get("content-type").unwrap(), "image/png");
+	assert!(res.headers().get("server").is_none());
+	assert!(res.headers().get("x-reddit-cdn").is_none());
+	assert_eq!(body::to_bytes(res.into_body()).await.unwrap(), png);
+
+	let jpeg = Response::builder().header("content-type", "image/jpeg").body(Body::empty()).unwrap();
+	assert!(!is_transparent(&jpeg));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_limit_size() {
+	let oversized = Response::builder().header("content-length", "2048").body(Body::from(vec![0u8; 2048])).unwrap();
+	assert_eq!(limit_size(oversized, 1024).status(), 413);
+
+	// Without a Content-Length, the body is cut off once it goes over the limit
+	let chunks: Vec<Result<Vec<u8>, io::Error>> = vec![Ok(vec![0u8; 512]), Ok(vec![0u8; 512]), Ok(vec![0u8; 512])];
+	let streamed = Response::new(Body::wrap_stream(futures_lite::stream::iter(chunks)));
+	let res = limit_size(streamed, 1024);
+	assert_eq!(res.status(), 200);
+	assert!(body::to_bytes(res.into_body()).await.is_err());
+
+	let small = Response::builder().header("content-length", "512").body(Body::from(vec![1u8; 512])).unwrap();
+	let res = limit_size(small, 1024);
+	assert_eq!(res.status(), 200);
+	assert_eq!(body::to_bytes(res.into_body()).await.unwrap().len(), 512);
+}
+
+#[cfg(test)]
+fn jpeg_with_orientation(orientation: u16, big_endian: bool) -> Vec<u8> {
+	let word = |value: u16| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+	let long = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+	let order: &[u8] = if big_endian { b"MM" } else { b"II" };
+
+	// TIFF header followed by an IFD with a single SHORT orientation entry
+	let tiff = [
+		order,
+		&word(42),
+		&long(8),
+		&word(1),
+		&word(0x0112),
+		&word(3),
+		&long(1),
+		&word(orientation),
+		&[0, 0],
+		&long(0),
+	]
+	.concat();
+	let exif = [&b"Exif\0\0"[..], &tiff].concat();
+	let len = u16::try_from(exif.len() + 2).unwrap().to_be_bytes();
+	[&[0xFF, 0xD8, 0xFF, 0xE1][..], &len, &exif, &[0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xD9]].concat()
+}
+
+#[test]
+fn test_exif_orientation() {
+	assert_eq!(exif_orientation(&jpeg_with_orientation(6, true)), Some(6));
+	assert_eq!(exif_orientation(&jpeg_with_orientation(3, false)), Some(3));
+	assert_eq!(exif_orientation(&[0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x02]), None);
+	assert_eq!(exif_orientation(b"\x89PNG\r\n\x1a\n"), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tag_orientation() {
+	let jpeg = jpeg_with_orientation(8, true);
+	let upstream = Response::builder().header("content-type", "image/jpeg").body(Body::from(jpeg.clone())).unwrap();
+
+	let res = tag_orientation(upstream).await;
+	assert_eq!(res.headers().get("X-Image-Orientation").unwrap(), "8");
+	assert_eq!(body::to_bytes(res.into_body()).await.unwrap(), jpeg);
+
+	// Parameters and the non-standard image/jpg are still recognised as JPEG
+	let upstream = Response::builder()
+		.header("content-type", "image/JPG; charset=binary")
+		.body(Body::from(jpeg_with_orientation(6, false)))
+		.unwrap();
+	assert_eq!(tag_orientation(upstream).await.headers().get("X-Image-Orientation").unwrap(), "6");
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_localization_popular() {
 	let val = json("/r/popular/hot.json?&raw_json=1&geo_filter=GLOBAL".to_string(), false).await.unwrap();
@@ -373,3 +822,57 @@ async fn test_share_link_strip_json() {
 	let canonical_link = "/r/nfl/comments/17krzvz/rapoport_sources_former_no_2_overall_pick/".into();
 	assert_eq!(canonical_path(link).await, Ok(Some(canonical_link)));
 }
+
+#[test]
+fn test_with_raw_json() {
+	assert_eq!(with_raw_json("/subreddits/search.json?q=rust&limit=3"), "/subreddits/search.json?q=rust&limit=3&raw_json=1");
+	assert_eq!(with_raw_json("/r/rust/about.json"), "/r/rust/about.json?raw_json=1");
+	assert_eq!(with_raw_json("/r/rust/hot.json?"), "/r/rust/hot.json?raw_json=1");
+	assert_eq!(with_raw_json("/r/rust/hot.json?&raw_json=1"), "/r/rust/hot.json?&raw_json=1");
+	assert_eq!(with_raw_json("/r/rust/hot.json?raw_json=1&after=t3_abc"), "/r/rust/hot.json?raw_json=1&after=t3_abc");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_subreddit_search_icons_unescaped() {
+	// Subreddit search doesn't ask for raw_json itself
+	let val = json("/subreddits/search.json?q=rust&limit=3".to_string(), false).await.unwrap();
+	for sub in val["data"]["children"].as_array().unwrap() {
+		let icon = sub["data"]["community_icon"].as_str().unwrap_or_default();
+		assert!(!icon.contains("&amp;"), "{icon}");
+		assert!(!format_url(icon).contains("&amp;"));
+	}
+}
+
+#[test]
+fn test_cache_control() {
+	assert_eq!(cache_control("https://i.redd.it/abc123.jpg", 600), "public, max-age=600, immutable");
+	assert_eq!(cache_control("https://preview.redd.it/abc123.png?width=640&s=def", 600), "public, max-age=600, immutable");
+	assert_eq!(cache_control("https://v.redd.it/abc123/DASH_720.mp4", 600), "public, max-age=600, immutable");
+	assert_eq!(cache_control("https://example.com/i.redd.it/abc123.jpg", 600), "no-cache");
+	assert_eq!(cache_control("https://external-preview.redd.it.example.com/abc123.jpg", 600), "no-cache");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_coalesce_identical_fetches() {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	let calls = Arc::new(AtomicUsize::new(0));
+	let fetch = || {
+		let calls = calls.clone();
+		move || async move {
+			calls.fetch_add(1, Ordering::SeqCst);
+			tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+			Ok(serde_json::json!({"kind": "Listing"}))
+		}
+	};
+
+	let key = ("/r/coalesce_test/hot.json".to_string(), false);
+	let (first, second) = tokio::join!(coalesce(key.clone(), fetch()), coalesce(key.clone(), fetch()));
+	assert_eq!(calls.load(Ordering::SeqCst), 1);
+	assert_eq!(first, second);
+	assert_eq!(first, Ok(serde_json::json!({"kind": "Listing"})));
+
+	// Once settled, the fetch is no longer shared
+	coalesce(key, fetch()).await.unwrap();
+	assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
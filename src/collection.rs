@@ -0,0 +1,150 @@
+// Handler for subreddit collections.
+
+use crate::client::json;
+use crate::server::RequestExt;
+use crate::subreddit::{can_access_quarantine, quarantine};
+use crate::utils::{
+	error, filter_blocked_authors, filter_nsfw_posts, filter_posts, filter_posts_by_domain, get_blocked_users, get_domain_filters, get_filters, parse_post, setting, template,
+	Post, Preferences,
+};
+
+use askama::Template;
+use hyper::{Body, Request, Response};
+use serde_json::Value;
+
+/// `Collection` is a curated, ordered set of posts put together by a
+/// subreddit's moderators.
+pub struct Collection {
+	/// id is the collection's UUID.
+	pub id: String,
+
+	/// title is the collection's title.
+	pub title: String,
+
+	/// description is the collection's description.
+	pub description: String,
+
+	/// post_ids are the IDs of the collection's posts, in collection order.
+	pub post_ids: Vec<String>,
+}
+
+impl Collection {
+	/// Parses a `Collection` from the JSON returned by Reddit's collection
+	/// endpoint.
+	pub fn parse(data: &Value) -> Self {
+		let field = |name: &str| data[name].as_str().unwrap_or_default().to_string();
+
+		Self {
+			id: field("collection_id"),
+			title: field("title"),
+			description: field("description"),
+			post_ids: data["link_ids"]
+				.as_array()
+				.map(|ids| ids.iter().filter_map(Value::as_str).map(|id| id.trim_start_matches("t3_").to_string()).collect())
+				.unwrap_or_default(),
+		}
+	}
+
+	/// Resolves the collection's posts from the listing Reddit includes in
+	/// the collection JSON, ordered as they are in the collection. Posts that
+	/// are missing from the listing are skipped.
+	pub async fn posts(&self, data: &Value) -> Vec<Post> {
+		let listing = data["sorted_links"]["data"]["children"].as_array().cloned().unwrap_or_default();
+
+		let mut posts = Vec::with_capacity(self.post_ids.len());
+		for id in &self.post_ids {
+			if let Some(post) = listing.iter().find(|post| post["data"]["id"].as_str() == Some(id)) {
+				posts.push(parse_post(post).await);
+			}
+		}
+		posts
+	}
+}
+
+/// `CollectionTemplate` defines an Askama template for rendering a
+/// collection.
+#[derive(Template)]
+#[template(path = "collection.html")]
+struct CollectionTemplate {
+	/// sub is the subreddit the collection belongs to.
+	sub: String,
+
+	/// collection is the collection being viewed.
+	collection: Collection,
+
+	/// posts are the collection's posts, in collection order.
+	posts: Vec<Post>,
+
+	/// prefs are the user preferences.
+	prefs: Preferences,
+
+	/// url is the request URL.
+	url: String,
+
+	/// all_posts_filtered is true if every post in the collection was
+	/// filtered.
+	all_posts_filtered: bool,
+}
+
+/// Fetches a collection and renders its posts in collection order.
+pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub = req.param("sub").unwrap_or_default();
+	let id = req.param("id").unwrap_or_default();
+	let quarantined = can_access_quarantine(&req, &sub);
+	let path = format!("/api/v1/collections/collection.json?collection_id={id}&include_links=true&raw_json=1");
+
+	match json(path, quarantined).await {
+		Ok(response) => {
+			let collection = Collection::parse(&response);
+			let mut posts = collection.posts(&response).await;
+
+			// Hide NSFW posts outright rather than gating the whole page, as
+			// collections may mix SFW and NSFW posts.
+			if setting(&req, "show_nsfw") != "on" || crate::utils::sfw_only() {
+				filter_nsfw_posts(&mut posts);
+			}
+			let (_, mut all_posts_filtered) = filter_posts(&mut posts, &get_filters(&req));
+			all_posts_filtered |= filter_blocked_authors(&mut posts, &get_blocked_users(&req)).1;
+			all_posts_filtered |= filter_posts_by_domain(&mut posts, &get_domain_filters(&req)).1;
+
+			Ok(template(&CollectionTemplate {
+				sub,
+				collection,
+				posts,
+				prefs: Preferences::new(&req),
+				url: req.uri().to_string(),
+				all_posts_filtered,
+			}))
+		}
+		Err(msg) => {
+			if msg == "quarantined" || msg == "gated" {
+				Ok(quarantine(&req, sub, &msg))
+			} else {
+				error(req, &msg).await
+			}
+		}
+	}
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_parse_collection() {
+	let response = serde_json::json!({
+		"collection_id": "c0ffee00-1234-5678-9abc-def012345678",
+		"title": "Weekly threads",
+		"description": "All of the weekly discussion threads",
+		"link_ids": ["t3_second", "t3_first", "t3_missing"],
+		"sorted_links": {"kind": "Listing", "data": {"children": [
+			{"kind": "t3", "data": {"id": "first", "title": "First"}},
+			{"kind": "t3", "data": {"id": "second", "title": "Second"}},
+		]}},
+	});
+
+	let collection = Collection::parse(&response);
+	assert_eq!(collection.id, "c0ffee00-1234-5678-9abc-def012345678");
+	assert_eq!(collection.title, "Weekly threads");
+	assert_eq!(collection.description, "All of the weekly discussion threads");
+	assert_eq!(collection.post_ids, ["second", "first", "missing"]);
+
+	let posts = collection.posts(&response).await;
+	assert_eq!(posts.iter().map(|post| post.title.as_str()).collect::<Vec<_>>(), ["Second", "First"]);
+}
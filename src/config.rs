@@ -91,6 +91,39 @@ pub struct Config {
 	#[serde(rename = "REDLIB_PUSHSHIFT_FRONTEND")]
 	#[serde(alias = "LIBREDDIT_PUSHSHIFT_FRONTEND")]
 	pub(crate) pushshift: Option<String>,
+
+	#[serde(rename = "REDLIB_PROXY_MAX_CONCURRENCY")]
+	pub(crate) proxy_max_concurrency: Option<String>,
+
+	#[serde(rename = "REDLIB_PROXY_FORCE_JPEG")]
+	pub(crate) proxy_force_jpeg: Option<String>,
+
+	#[serde(rename = "REDLIB_PROXY_MAX_BYTES")]
+	pub(crate) proxy_max_bytes: Option<String>,
+
+	#[serde(rename = "REDLIB_PROXY_APPLY_EXIF_ROTATION")]
+	pub(crate) proxy_apply_exif_rotation: Option<String>,
+
+	#[serde(rename = "REDLIB_NSFW_BYPASS_DURATION")]
+	pub(crate) nsfw_bypass_duration: Option<String>,
+
+	#[serde(rename = "REDLIB_STREAM_COMMENTS_THRESHOLD")]
+	pub(crate) stream_comments_threshold: Option<String>,
+
+	#[serde(rename = "REDLIB_FALLBACK_AVATAR")]
+	pub(crate) fallback_avatar: Option<String>,
+
+	#[serde(rename = "REDLIB_PROXY_CACHE_MAX_AGE")]
+	pub(crate) proxy_cache_max_age: Option<String>,
+
+	#[serde(rename = "REDLIB_NOINDEX_NSFW")]
+	pub(crate) noindex_nsfw: Option<String>,
+
+	#[serde(rename = "REDLIB_FORCE_THEME")]
+	pub(crate) force_theme: Option<String>,
+
+	#[serde(rename = "REDLIB_COALESCE_REQUESTS")]
+	pub(crate) coalesce_requests: Option<String>,
 }
 
 impl Config {
@@ -125,7 +158,7 @@ impl Config {
 			default_show_nsfw: parse("REDLIB_DEFAULT_SHOW_NSFW"),
 			default_blur_nsfw: parse("REDLIB_DEFAULT_BLUR_NSFW"),
 			default_use_hls: parse("REDLIB_DEFAULT_USE_HLS"),
-			default_hide_hls_notification: parse("REDLIB_DEFAULT_HIDE_HLS"),
+			default_hide_hls_notification: parse("REDLIB_DEFAULT_HIDE_HLS_NOTIFICATION"),
 			default_hide_awards: parse("REDLIB_DEFAULT_HIDE_AWARDS"),
 			default_hide_score: parse("REDLIB_DEFAULT_HIDE_SCORE"),
 			default_subscriptions: parse("REDLIB_DEFAULT_SUBSCRIPTIONS"),
@@ -133,6 +166,17 @@ impl Config {
 			banner: parse("REDLIB_BANNER"),
 			robots_disable_indexing: parse("REDLIB_ROBOTS_DISABLE_INDEXING"),
 			pushshift: parse("REDLIB_PUSHSHIFT_FRONTEND"),
+			proxy_max_concurrency: parse("REDLIB_PROXY_MAX_CONCURRENCY"),
+			proxy_force_jpeg: parse("REDLIB_PROXY_FORCE_JPEG"),
+			proxy_max_bytes: parse("REDLIB_PROXY_MAX_BYTES"),
+			proxy_apply_exif_rotation: parse("REDLIB_PROXY_APPLY_EXIF_ROTATION"),
+			nsfw_bypass_duration: parse("REDLIB_NSFW_BYPASS_DURATION"),
+			stream_comments_threshold: parse("REDLIB_STREAM_COMMENTS_THRESHOLD"),
+			fallback_avatar: parse("REDLIB_FALLBACK_AVATAR"),
+			proxy_cache_max_age: parse("REDLIB_PROXY_CACHE_MAX_AGE"),
+			noindex_nsfw: parse("REDLIB_NOINDEX_NSFW"),
+			force_theme: parse("REDLIB_FORCE_THEME"),
+			coalesce_requests: parse("REDLIB_COALESCE_REQUESTS"),
 		}
 	}
 }
@@ -157,6 +201,17 @@ fn get_setting_from_config(name: &str, config: &Config) -> Option<String> {
 		"REDLIB_BANNER" => config.banner.clone(),
 		"REDLIB_ROBOTS_DISABLE_INDEXING" => config.robots_disable_indexing.clone(),
 		"REDLIB_PUSHSHIFT_FRONTEND" => config.pushshift.clone(),
+		"REDLIB_PROXY_MAX_CONCURRENCY" => config.proxy_max_concurrency.clone(),
+		"REDLIB_PROXY_FORCE_JPEG" => config.proxy_force_jpeg.clone(),
+		"REDLIB_PROXY_MAX_BYTES" => config.proxy_max_bytes.clone(),
+		"REDLIB_PROXY_APPLY_EXIF_ROTATION" => config.proxy_apply_exif_rotation.clone(),
+		"REDLIB_NSFW_BYPASS_DURATION" => config.nsfw_bypass_duration.clone(),
+		"REDLIB_STREAM_COMMENTS_THRESHOLD" => config.stream_comments_threshold.clone(),
+		"REDLIB_FALLBACK_AVATAR" => config.fallback_avatar.clone(),
+		"REDLIB_PROXY_CACHE_MAX_AGE" => config.proxy_cache_max_age.clone(),
+		"REDLIB_NOINDEX_NSFW" => config.noindex_nsfw.clone(),
+		"REDLIB_FORCE_THEME" => config.force_theme.clone(),
+		"REDLIB_COALESCE_REQUESTS" => config.coalesce_requests.clone(),
 		_ => None,
 	}
 }
@@ -233,3 +288,80 @@ fn test_pushshift() {
 	assert!(get_setting("REDLIB_PUSHSHIFT_FRONTEND").is_some());
 	assert_eq!(get_setting("REDLIB_PUSHSHIFT_FRONTEND"), Some("https://api.pushshift.io".into()));
 }
+
+#[test]
+#[sealed_test(env = [("REDLIB_DEFAULT_HIDE_HLS_NOTIFICATION", "on")])]
+fn test_default_hide_hls_notification() {
+	use hyper::{Body, Request};
+
+	assert_eq!(get_setting("REDLIB_DEFAULT_HIDE_HLS_NOTIFICATION"), Some("on".into()));
+
+	// The instance default applies when the user has no cookie
+	let req = Request::builder().body(Body::empty()).unwrap();
+	assert_eq!(crate::utils::Preferences::new(&req).hide_hls_notification, "on");
+
+	// An explicit user choice still wins
+	let req = Request::builder().header("Cookie", "hide_hls_notification=off").body(Body::empty()).unwrap();
+	assert_eq!(crate::utils::Preferences::new(&req).hide_hls_notification, "off");
+}
+
+#[test]
+#[sealed_test(env = [("REDLIB_DEFAULT_WIDE", "on")])]
+fn test_default_wide() {
+	use hyper::{Body, Request};
+
+	// The instance default applies when the user has no cookie
+	let req = Request::builder().body(Body::empty()).unwrap();
+	assert_eq!(crate::utils::Preferences::new(&req).wide, "on");
+
+	// An explicit user choice still wins
+	let req = Request::builder().header("Cookie", "wide=off").body(Body::empty()).unwrap();
+	assert_eq!(crate::utils::Preferences::new(&req).wide, "off");
+}
+
+#[test]
+#[sealed_test]
+fn test_wide_off_without_default() {
+	use hyper::{Body, Request};
+
+	let req = Request::builder().body(Body::empty()).unwrap();
+	assert_eq!(crate::utils::Preferences::new(&req).wide, "off");
+}
+
+#[test]
+#[sealed_test(env = [("REDLIB_DEFAULT_COMMENT_SORT", "top")])]
+fn test_default_comment_sort() {
+	use hyper::{Body, Request};
+
+	// The instance default applies when the user has no cookie
+	let req = Request::builder().body(Body::empty()).unwrap();
+	assert_eq!(crate::utils::Preferences::new(&req).comment_sort, "top");
+
+	// An explicit user choice still wins
+	let req = Request::builder().header("Cookie", "comment_sort=new").body(Body::empty()).unwrap();
+	assert_eq!(crate::utils::Preferences::new(&req).comment_sort, "new");
+}
+
+#[test]
+#[sealed_test(env = [("REDLIB_FORCE_THEME", "dracula")])]
+fn test_force_theme() {
+	use hyper::{Body, Request};
+
+	// The forced theme wins over the user's own choice
+	let req = Request::builder().header("Cookie", "theme=light").body(Body::empty()).unwrap();
+	let prefs = crate::utils::Preferences::new(&req);
+	assert_eq!(prefs.theme, "dracula");
+	assert!(prefs.theme_locked);
+	assert!(prefs.available_themes.contains(&"light".to_string()));
+}
+
+#[test]
+#[sealed_test(env = [("REDLIB_FORCE_THEME", "nonexistent")])]
+fn test_force_unknown_theme() {
+	use hyper::{Body, Request};
+
+	let req = Request::builder().header("Cookie", "theme=light").body(Body::empty()).unwrap();
+	let prefs = crate::utils::Preferences::new(&req);
+	assert_eq!(prefs.theme, "light");
+	assert!(!prefs.theme_locked);
+}
@@ -3,13 +3,15 @@
 use crate::client::json;
 use crate::server::RequestExt;
 use crate::subreddit::{can_access_quarantine, quarantine};
-use crate::utils::{error, filter_posts, get_filters, nsfw_landing, parse_post, template, Post, Preferences};
+use crate::utils::{
+	error, filter_blocked_authors, filter_posts, filter_posts_by_domain, get_blocked_users, get_domain_filters, get_filters, nsfw_landing, parse_post, template, Post,
+	Preferences,
+};
 
 use askama::Template;
 use hyper::{Body, Request, Response};
 use serde_json::Value;
 use std::borrow::ToOwned;
-use std::collections::HashSet;
 use std::vec::Vec;
 
 /// `DuplicatesParams` contains the parameters in the URL.
@@ -53,19 +55,16 @@ struct DuplicatesTemplate {
 /// Make the GET request to Reddit. It assumes `req` is the appropriate Reddit
 /// REST endpoint for enumerating post duplicates.
 pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
-	let path: String = format!("{}.json?{}&raw_json=1", req.uri().path(), req.uri().query().unwrap_or_default());
+	let id = req.param("id").unwrap_or_default();
+	let path: String = format!("{}.json?{}&raw_json=1", duplicates_path(&id), req.uri().query().unwrap_or_default());
 	let sub = req.param("sub").unwrap_or_default();
 	let quarantined = can_access_quarantine(&req, &sub);
 
-	// Log the request in debugging mode
-	#[cfg(debug_assertions)]
-	req.param("id").unwrap_or_default();
-
 	// Send the GET, and await JSON.
 	match json(path, quarantined).await {
 		// Process response JSON.
 		Ok(response) => {
-			let post = parse_post(&response[0]["data"]["children"][0]).await;
+			let (post, mut duplicates) = parse_duplicates(&response).await;
 
 			let req_url = req.uri().to_string();
 			// Return landing page if this post if this Reddit deems this post
@@ -75,8 +74,13 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 				return Ok(nsfw_landing(req, req_url).await.unwrap_or_default());
 			}
 
-			let filters = get_filters(&req);
-			let (duplicates, num_posts_filtered, all_posts_filtered) = parse_duplicates(&response[1], &filters).await;
+			let (mut num_posts_filtered, mut all_posts_filtered) = filter_posts(&mut duplicates, &get_filters(&req));
+			let (num_posts_blocked, all_posts_blocked) = filter_blocked_authors(&mut duplicates, &get_blocked_users(&req));
+			num_posts_filtered += num_posts_blocked;
+			all_posts_filtered |= all_posts_blocked;
+			let (num_domains_filtered, all_domains_filtered) = filter_posts_by_domain(&mut duplicates, &get_domain_filters(&req));
+			num_posts_filtered += num_domains_filtered;
+			all_posts_filtered |= all_domains_filtered;
 
 			// These are the values for the "before=", "after=", and "sort="
 			// query params, respectively.
@@ -175,7 +179,7 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 					// We'll mitigate that by requesting at most one duplicate.
 					let new_path: String = format!(
 						"{}.json?before=t3_{}&sort={}&limit=1&raw_json=1",
-						req.uri().path(),
+						duplicates_path(&id),
 						&duplicates[0].id,
 						if sort.is_empty() { "num_comments".to_string() } else { sort.clone() }
 					);
@@ -220,9 +224,18 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 }
 
+/// Returns the Reddit endpoint listing the duplicates (other discussions) of
+/// the post with ID `post_id`.
+pub fn duplicates_path(post_id: &str) -> String {
+	format!("/duplicates/{post_id}")
+}
+
 // DUPLICATES
-async fn parse_duplicates(json: &Value, filters: &HashSet<String>) -> (Vec<Post>, u64, bool) {
-	let post_duplicates: &Vec<Value> = &json["data"]["children"].as_array().map_or(Vec::new(), ToOwned::to_owned);
+/// Parses the response of the duplicates endpoint into the post whose
+/// duplicates were requested and the list of its duplicates.
+async fn parse_duplicates(json: &Value) -> (Post, Vec<Post>) {
+	let post = parse_post(&json[0]["data"]["children"][0]).await;
+	let post_duplicates: &Vec<Value> = &json[1]["data"]["children"].as_array().map_or(Vec::new(), ToOwned::to_owned);
 	let mut duplicates: Vec<Post> = Vec::new();
 
 	// Process each post and place them in the Vec<Post>.
@@ -231,6 +244,38 @@ async fn parse_duplicates(json: &Value, filters: &HashSet<String>) -> (Vec<Post>
 		duplicates.push(post);
 	}
 
-	let (num_posts_filtered, all_posts_filtered) = filter_posts(&mut duplicates, filters);
-	(duplicates, num_posts_filtered, all_posts_filtered)
+	(post, duplicates)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{duplicates_path, parse_duplicates};
+	use serde_json::json;
+
+	#[test]
+	fn test_duplicates_path() {
+		assert_eq!(duplicates_path("1abcde"), "/duplicates/1abcde");
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_duplicates() {
+		let response = json!([
+			{"kind": "Listing", "data": {"children": [
+				{"kind": "t3", "data": {"id": "orig01", "title": "Original", "subreddit": "rust", "num_duplicates": 2}}
+			]}},
+			{"kind": "Listing", "data": {"after": null, "children": [
+				{"kind": "t3", "data": {"id": "dupe01", "title": "First duplicate", "subreddit": "programming"}},
+				{"kind": "t3", "data": {"id": "dupe02", "title": "Second duplicate", "subreddit": "coding"}}
+			]}}
+		]);
+
+		let (post, duplicates) = parse_duplicates(&response).await;
+		assert_eq!(post.id, "orig01");
+		assert_eq!(post.num_duplicates, 2);
+		assert_eq!(duplicates.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["dupe01", "dupe02"]);
+		assert_eq!(duplicates[1].community, "coding");
+
+		let (_, duplicates) = parse_duplicates(&json!([{"data": {"children": []}}])).await;
+		assert!(duplicates.is_empty());
+	}
 }
@@ -126,6 +126,17 @@ impl InstanceInfo {
 				["Compile mode", &self.compile_mode],
 				["SFW only", &convert(&self.config.sfw_only)],
 				["Pushshift frontend", &convert(&self.config.pushshift)],
+				["Proxy max concurrency", &convert(&self.config.proxy_max_concurrency)],
+				["Proxy force JPEG", &convert(&self.config.proxy_force_jpeg)],
+				["Proxy max bytes", &convert(&self.config.proxy_max_bytes)],
+				["Proxy apply EXIF rotation", &convert(&self.config.proxy_apply_exif_rotation)],
+				["NSFW bypass duration", &convert(&self.config.nsfw_bypass_duration)],
+				["Stream comments threshold", &convert(&self.config.stream_comments_threshold)],
+				["Fallback avatar", &convert(&self.config.fallback_avatar)],
+				["Proxy cache max age", &convert(&self.config.proxy_cache_max_age)],
+				["No-index NSFW", &convert(&self.config.noindex_nsfw)],
+				["Force theme", &convert(&self.config.force_theme)],
+				["Coalesce requests", &convert(&self.config.coalesce_requests)],
 				//TODO: fallback to crate::config::DEFAULT_PUSHSHIFT_FRONTEND
 			])
 			.with_header_row(["Settings"]),
@@ -163,6 +174,17 @@ impl InstanceInfo {
                 Compile mode: {}\n
 				SFW only: {:?}\n
 				Pushshift frontend: {:?}\n
+				Proxy max concurrency: {:?}\n
+				Proxy force JPEG: {:?}\n
+				Proxy max bytes: {:?}\n
+				Proxy apply EXIF rotation: {:?}\n
+				NSFW bypass duration: {:?}\n
+				Stream comments threshold: {:?}\n
+				Default avatar: {:?}\n
+				Proxy cache max age: {:?}\n
+				No-index NSFW: {:?}\n
+				Force theme: {:?}\n
+				Coalesce requests: {:?}\n
                 Config:\n
                     Banner: {:?}\n
                     Hide awards: {:?}\n
@@ -186,6 +208,17 @@ impl InstanceInfo {
 					self.compile_mode,
 					self.config.sfw_only,
 					self.config.pushshift,
+					self.config.proxy_max_concurrency,
+					self.config.proxy_force_jpeg,
+					self.config.proxy_max_bytes,
+					self.config.proxy_apply_exif_rotation,
+					self.config.nsfw_bypass_duration,
+					self.config.stream_comments_threshold,
+					self.config.fallback_avatar,
+					self.config.proxy_cache_max_age,
+					self.config.noindex_nsfw,
+					self.config.force_theme,
+					self.config.coalesce_requests,
 					self.config.banner,
 					self.config.default_hide_awards,
 					self.config.default_hide_score,
@@ -3,6 +3,7 @@
 #![allow(clippy::cmp_owned)]
 
 // Reference local files
+mod collection;
 mod config;
 mod duplicates;
 mod instance_info;
@@ -56,6 +57,11 @@ async fn iphone_logo() -> Result<Response<Body>, String> {
 	)
 }
 
+// Libreddit served the iOS icon here
+async fn legacy_iphone_logo() -> Result<Response<Body>, String> {
+	Ok(redirect("/apple-touch-icon.png"))
+}
+
 async fn favicon() -> Result<Response<Body>, String> {
 	Ok(
 		Response::builder()
@@ -221,8 +227,8 @@ async fn main() {
 	app.at("/favicon.ico").get(|_| favicon().boxed());
 	app.at("/logo.png").get(|_| pwa_logo().boxed());
 	app.at("/Inter.var.woff2").get(|_| font().boxed());
-	app.at("/touch-icon-iphone.png").get(|_| iphone_logo().boxed());
 	app.at("/apple-touch-icon.png").get(|_| iphone_logo().boxed());
+	app.at("/touch-icon-iphone.png").get(|_| legacy_iphone_logo().boxed());
 	app
 		.at("/playHLSVideo.js")
 		.get(|_| resource(include_str!("../static/playHLSVideo.js"), "text/javascript", false).boxed());
@@ -245,6 +251,9 @@ async fn main() {
 	app.at("/preview/:loc/:id").get(|r| proxy(r, "https://{loc}view.redd.it/{id}").boxed());
 	app.at("/style/*path").get(|r| proxy(r, "https://styles.redditmedia.com/{path}").boxed());
 	app.at("/static/*path").get(|r| proxy(r, "https://www.redditstatic.com/{path}").boxed());
+	app
+		.at("/emote/*path")
+		.get(|r| proxy(r, "https://reddit-econ-prod-assets-permanent.s3.amazonaws.com/{path}").boxed());
 
 	// Browse user profile
 	app
@@ -255,6 +264,7 @@ async fn main() {
 
 	app.at("/user/[deleted]").get(|req| error(req, "User has deleted their account").boxed());
 	app.at("/user/:name").get(|r| user::profile(r).boxed());
+	app.at("/user/:name/about.json").get(|r| user::about_json(r).boxed());
 	app.at("/user/:name/:listing").get(|r| user::profile(r).boxed());
 	app.at("/user/:name/comments/:id").get(|r| post::item(r).boxed());
 	app.at("/user/:name/comments/:id/:title").get(|r| post::item(r).boxed());
@@ -264,6 +274,7 @@ async fn main() {
 	app.at("/settings").get(|r| settings::get(r).boxed()).post(|r| settings::set(r).boxed());
 	app.at("/settings/restore").get(|r| settings::restore(r).boxed());
 	app.at("/settings/update").get(|r| settings::update(r).boxed());
+	app.at("/nsfw_bypass").get(|r| settings::bypass_nsfw(r).boxed());
 
 	// Subreddit services
 	app
@@ -289,6 +300,8 @@ async fn main() {
 	app.at("/comments/:id/:title").get(|r| post::item(r).boxed());
 	app.at("/comments/:id/:title/:comment_id").get(|r| post::item(r).boxed());
 
+	app.at("/r/:sub/collection/:id").get(|r| collection::item(r).boxed());
+
 	app.at("/r/:sub/duplicates/:id").get(|r| duplicates::item(r).boxed());
 	app.at("/r/:sub/duplicates/:id/:title").get(|r| duplicates::item(r).boxed());
 	app.at("/duplicates/:id").get(|r| duplicates::item(r).boxed());
@@ -306,6 +319,7 @@ async fn main() {
 	app.at("/r/:sub/wiki/*page").get(|r| subreddit::wiki(r).boxed());
 
 	app.at("/r/:sub/about/sidebar").get(|r| subreddit::sidebar(r).boxed());
+	app.at("/r/:sub/about.json").get(|r| subreddit::about_json(r).boxed());
 
 	app.at("/r/:sub/:sort").get(|r| subreddit::community(r).boxed());
 
@@ -382,3 +396,10 @@ async fn main() {
 		eprintln!("Server error: {e}");
 	}
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_legacy_iphone_logo() {
+	let res = legacy_iphone_logo().await.unwrap();
+	assert_eq!(res.status(), 302);
+	assert_eq!(res.headers().get("Location").unwrap(), "/apple-touch-icon.png");
+}
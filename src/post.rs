@@ -3,15 +3,20 @@ use crate::client::json;
 use crate::config::get_setting;
 use crate::server::RequestExt;
 use crate::subreddit::{can_access_quarantine, quarantine};
+use crate::user::fetch_author_summary;
 use crate::utils::{
-	error, format_num, get_filters, nsfw_landing, param, parse_post, rewrite_urls, setting, template, time, val, Author, Awards, Comment, Flair, FlairPart, Post, Preferences,
+	comment_permalink, constrain_images, error, format_score, get_filters, nsfw_landing, param, parse_post, post_body, raw_markdown, render_giphy, rewrite_urls, template, time,
+	val, Author, Awards, Comment, Flair, Post, Preferences,
 };
 use hyper::{Body, Request, Response};
 
 use askama::Template;
+use futures_lite::{stream, StreamExt};
 use once_cell::sync::Lazy;
 use regex::Regex;
+use std::cmp::Ordering;
 use std::collections::HashSet;
+use std::convert::Infallible;
 
 // STRUCTS
 #[derive(Template)]
@@ -25,8 +30,26 @@ struct PostTemplate {
 	url: String,
 	url_without_query: String,
 	comment_query: String,
+	/// host is the instance's host, as requested by the client.
+	host: String,
+	/// Leave a marker where the comments go, for `stream_template` to fill in.
+	stream_comments: bool,
 }
 
+/// A single top-level comment thread, rendered on its own when streaming.
+#[derive(Template)]
+#[template(path = "thread.html")]
+struct ThreadTemplate {
+	c: Comment,
+	single_thread: bool,
+}
+
+// Where the comments of a streamed page are spliced in
+const STREAMED_COMMENTS_MARKER: &str = "<!-- STREAMED COMMENTS -->";
+
+// Used when REDLIB_STREAM_COMMENTS_THRESHOLD is unset or invalid.
+const DEFAULT_STREAM_COMMENTS_THRESHOLD: usize = 1000;
+
 static COMMENT_SEARCH_CAPTURE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\?q=(.*)&type=comment").unwrap());
 
 pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
@@ -35,34 +58,56 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 	let sub = req.param("sub").unwrap_or_default();
 	let quarantined = can_access_quarantine(&req, &sub);
 	let url = req.uri().to_string();
-
-	// Set sort to sort query parameter
-	let sort = param(&path, "sort").unwrap_or_else(|| {
-		// Grab default comment sort method from Cookies
-		let default_sort = setting(&req, "comment_sort");
-
-		// If there's no sort query but there's a default sort, set sort to default_sort
-		if default_sort.is_empty() {
-			String::new()
-		} else {
-			path = format!("{}.json?{}&sort={}&raw_json=1", req.uri().path(), req.uri().query().unwrap_or_default(), default_sort);
-			default_sort
-		}
-	});
+	let host = req.headers().get("Host").and_then(|host| host.to_str().ok()).unwrap_or_default().to_string();
+
+	// Set sort to sort query parameter, or else the user's default sort or the instance's
+	let query_sort = param(&path, "sort");
+	let user_sort = req.cookie("comment_sort").map(|cookie| cookie.value().to_string());
+	let instance_sort = get_setting("REDLIB_DEFAULT_COMMENT_SORT");
+	let sort_path = |sort: &str| format!("{}.json?{}&sort={sort}&raw_json=1", req.uri().path(), req.uri().query().unwrap_or_default());
+	let mut sort = query_sort
+		.clone()
+		.unwrap_or_else(|| resolve_comment_sort(user_sort.as_deref(), None, instance_sort.as_deref()));
+	if query_sort.is_none() && !sort.is_empty() {
+		path = sort_path(&sort);
+	}
 
 	// Log the post ID being fetched in debug mode
 	#[cfg(debug_assertions)]
 	req.param("id").unwrap_or_default();
 
 	let single_thread = req.param("comment_id").is_some();
+	let prefs = Preferences::new(&req);
 	let highlighted_comment = &req.param("comment_id").unwrap_or_default();
 
 	// Send a request to the url, receive JSON in response
 	match json(path, quarantined).await {
 		// Otherwise, grab the JSON output from the request
-		Ok(response) => {
+		Ok(mut response) => {
+			// The subreddit's suggested sort is only known now. It beats the
+			// instance default, so fetch the comments again if it applies.
+			if query_sort.is_none() {
+				let suggested = response[0]["data"]["children"][0]["data"]["suggested_sort"].as_str();
+				let resolved = resolve_comment_sort(user_sort.as_deref(), suggested, instance_sort.as_deref());
+				if sort.is_empty() && suggested == Some(resolved.as_str()) {
+					// Reddit already applies the suggested sort when none is given
+					sort = resolved;
+				} else if resolved != sort {
+					if let Ok(resorted) = json(sort_path(&resolved), quarantined).await {
+						response = resorted;
+						sort = resolved;
+					}
+				}
+			}
+
 			// Parse the JSON into Post and Comment structs
-			let post = parse_post(&response[0]["data"]["children"][0]).await;
+			let mut post = parse_post(&response[0]["data"]["children"][0]).await;
+			if prefs.show_raw_markdown == "on" {
+				post.body = post_body(&response[0]["data"]["children"][0], true);
+			}
+			if prefs.constrain_body_images == "on" {
+				post.body = constrain_images(&post.body);
+			}
 
 			let req_url = req.uri().to_string();
 			// Return landing page if this post if this Reddit deems this post
@@ -72,6 +117,14 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 				return Ok(nsfw_landing(req, req_url).await.unwrap_or_default());
 			}
 
+			// Costs a request per post, so hovercards are opt-in. Only the post's
+			// author gets one, as a card per commenter would cost a request each.
+			// The card is fetched while the comments are parsed.
+			let summary = (prefs.author_hovercards == "on" && post.author.name != "[deleted]").then(|| {
+				let name = post.author.name.clone();
+				tokio::spawn(async move { fetch_author_summary(&name).await })
+			});
+
 			let query = match COMMENT_SEARCH_CAPTURE.captures(&url) {
 				Some(captures) => captures.get(1).unwrap().as_str().replace("%20", " ").replace('+', " "),
 				None => String::new(),
@@ -79,20 +132,33 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 
 			let comments = match query.as_str() {
 				"" => parse_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &req),
-				_ => query_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &query, &req),
+				_ => {
+					// Matches are collected from across the tree, so put them back in the requested order
+					let mut matches = query_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &query, &req);
+					sort_comment_tree(&mut matches, &sort);
+					matches
+				}
 			};
 
+			let stream = count_comments(&comments) > stream_comments_threshold();
+			if let Some(summary) = summary {
+				post.author.summary = summary.await.ok().flatten();
+			}
+
 			// Use the Post and Comment structs to generate a website to show users
-			Ok(template(&PostTemplate {
+			let page = PostTemplate {
 				comments,
 				post,
 				url_without_query: url.clone().trim_end_matches(&format!("?q={query}&type=comment")).to_string(),
 				sort,
-				prefs: Preferences::new(&req),
+				prefs,
 				single_thread,
 				url: req_url,
 				comment_query: query,
-			}))
+				host,
+				stream_comments: false,
+			};
+			Ok(if stream { stream_template(page) } else { template(&page) })
 		}
 		// If the Reddit API returns an error, exit and send error page to user
 		Err(msg) => {
@@ -106,6 +172,68 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 }
 
+/// Picks the comment sort for a post without a `sort` query parameter: the
+/// user's preference, then the subreddit's suggested sort, then the instance
+/// default. Returns an empty string, leaving the choice to Reddit, if none is set.
+pub fn resolve_comment_sort(user: Option<&str>, suggested: Option<&str>, instance: Option<&str>) -> String {
+	[user, suggested, instance]
+		.into_iter()
+		.flatten()
+		.find(|sort| !sort.is_empty())
+		.unwrap_or_default()
+		.to_string()
+}
+
+/// Renders `page` with its comments streamed to the client one top-level
+/// thread at a time, rather than building the whole page in one string.
+fn stream_template(mut page: PostTemplate) -> Response<Body> {
+	let comments = std::mem::take(&mut page.comments);
+	let single_thread = page.single_thread;
+	page.stream_comments = true;
+
+	let rendered = page.render().unwrap_or_default();
+	let (head, tail) = rendered.split_once(STREAMED_COMMENTS_MARKER).unwrap_or((&rendered, ""));
+	let (head, tail) = (head.to_string(), tail.to_string());
+
+	let threads = comments.into_iter().map(move |c| ThreadTemplate { c, single_thread }.render().unwrap_or_default());
+	let body = stream::once(head).chain(stream::iter(threads)).chain(stream::once(tail)).map(Ok::<_, Infallible>);
+
+	Response::builder()
+		.status(200)
+		.header("content-type", "text/html")
+		.body(Body::wrap_stream(body))
+		.unwrap_or_default()
+}
+
+/// The number of comments above which a thread is streamed.
+fn stream_comments_threshold() -> usize {
+	get_setting("REDLIB_STREAM_COMMENTS_THRESHOLD")
+		.and_then(|val| val.parse::<usize>().ok())
+		.unwrap_or(DEFAULT_STREAM_COMMENTS_THRESHOLD)
+}
+
+/// Reorders `comments` and, recursively, their replies by `sort`: "top" by
+/// score, "new" and "old" by time posted. Stickied comments stay first and
+/// "more replies" links stay last. Other sorts leave the order untouched.
+pub fn sort_comment_tree(comments: &mut [Comment], sort: &str) {
+	let order: fn(&Comment, &Comment) -> Ordering = match sort {
+		"top" => |a, b| b.score_value.cmp(&a.score_value),
+		"new" => |a, b| b.created_unix.total_cmp(&a.created_unix),
+		"old" => |a, b| a.created_unix.total_cmp(&b.created_unix),
+		_ => return,
+	};
+
+	comments.sort_by(|a, b| b.stickied.cmp(&a.stickied).then((a.kind == "more").cmp(&(b.kind == "more"))).then_with(|| order(a, b)));
+	for comment in comments.iter_mut() {
+		sort_comment_tree(&mut comment.replies, sort);
+	}
+}
+
+/// Counts `comments` and all of their replies.
+fn count_comments(comments: &[Comment]) -> usize {
+	comments.iter().map(|c| 1 + count_comments(&c.replies)).sum()
+}
+
 // COMMENTS
 
 fn parse_comments(json: &serde_json::Value, post_link: &str, post_author: &str, highlighted_comment: &str, filters: &HashSet<String>, req: &Request<Body>) -> Vec<Comment> {
@@ -167,15 +295,20 @@ fn build_comment(
 	req: &Request<Body>,
 ) -> Comment {
 	let id = val(comment, "id");
+	let prefs = Preferences::new(req);
 
 	let body = if (val(comment, "author") == "[deleted]" && val(comment, "body") == "[removed]") || val(comment, "body") == "[ Removed by Reddit ]" {
 		format!(
-			"<div class=\"md\"><p>[removed] — <a href=\"https://{}{post_link}{id}\">view removed comment</a></p></div>",
+			"<div class=\"md\"><p>[removed] — <a href=\"https://{}{}\">view removed comment</a></p></div>",
 			get_setting("REDLIB_PUSHSHIFT_FRONTEND").unwrap_or_else(|| String::from(crate::config::DEFAULT_PUSHSHIFT_FRONTEND)),
+			comment_permalink(post_link, &id),
 		)
+	} else if prefs.show_raw_markdown == "on" {
+		raw_markdown(&val(comment, "body"))
 	} else {
-		rewrite_urls(&val(comment, "body_html"))
+		render_giphy(&rewrite_urls(&val(comment, "body_html")), &data["media_metadata"])
 	};
+	let body = if prefs.constrain_body_images == "on" { constrain_images(&body) } else { body };
 	let kind = comment["kind"].as_str().unwrap_or_default().to_string();
 
 	let unix_time = data["created_utc"].as_f64().unwrap_or_default();
@@ -192,7 +325,7 @@ fn build_comment(
 	// Note that in certain (seemingly random) cases, the count is simply wrong.
 	let more_count = data["count"].as_i64().unwrap_or_default();
 
-	let awards: Awards = Awards::parse(&data["all_awardings"]);
+	let awards: Awards = Awards::parse(&data["all_awardings"], &data["gildings"]);
 
 	let parent_kind_and_id = val(comment, "parent_id");
 	let parent_info = parent_kind_and_id.split('_').collect::<Vec<&str>>();
@@ -201,17 +334,9 @@ fn build_comment(
 
 	let author = Author {
 		name: val(comment, "author"),
-		flair: Flair {
-			flair_parts: FlairPart::parse(
-				data["author_flair_type"].as_str().unwrap_or_default(),
-				data["author_flair_richtext"].as_array(),
-				data["author_flair_text"].as_str(),
-			),
-			text: val(comment, "link_flair_text"),
-			background_color: val(comment, "author_flair_background_color"),
-			foreground_color: val(comment, "author_flair_text_color"),
-		},
+		flair: Flair::parse_author(data),
 		distinguished: val(comment, "distinguished"),
+		summary: None,
 	};
 	let is_filtered = filters.contains(&["u_", author.name.as_str()].concat());
 
@@ -221,7 +346,8 @@ fn build_comment(
 	// collapse stickied moderator comments.
 	let is_moderator_comment = data["distinguished"].as_str().unwrap_or_default() == "moderator";
 	let is_stickied = data["stickied"].as_bool().unwrap_or_default();
-	let collapsed = (is_moderator_comment && is_stickied) || is_filtered;
+	let collapse_reason = collapse_reason(data);
+	let collapsed = (is_moderator_comment && is_stickied) || is_filtered || collapse_reason.is_some();
 
 	Comment {
 		id,
@@ -232,11 +358,7 @@ fn build_comment(
 		post_author: post_author.to_string(),
 		body,
 		author,
-		score: if data["score_hidden"].as_bool().unwrap_or_default() {
-			("\u{2022}".to_string(), "Hidden".to_string())
-		} else {
-			format_num(score)
-		},
+		score: format_score(score, data["score_hidden"].as_bool().unwrap_or_default()),
 		rel_time,
 		created,
 		edited,
@@ -244,8 +366,232 @@ fn build_comment(
 		highlighted,
 		awards,
 		collapsed,
+		collapse_reason,
 		is_filtered,
 		more_count,
-		prefs: Preferences::new(req),
+		score_value: score,
+		created_unix: unix_time,
+		stickied: is_stickied,
+		prefs,
+	}
+}
+
+/// Returns why Reddit collapsed a comment, if it did, as a label to show next
+/// to it: crowd control is told apart from collapsing for a low score.
+fn collapse_reason(data: &serde_json::Value) -> Option<String> {
+	let code = data["collapsed_reason_code"].as_str().unwrap_or_default();
+	if data["collapsed_because_crowd_control"].as_bool().unwrap_or_default() || code.eq_ignore_ascii_case("crowd_control") {
+		return Some("collapsed by crowd control".to_string());
+	}
+	if !data["collapsed"].as_bool().unwrap_or_default() {
+		return None;
+	}
+	match code.to_ascii_lowercase().as_str() {
+		"low_score" => Some("collapsed for low score".to_string()),
+		_ => data["collapsed_reason"].as_str().filter(|reason| !reason.is_empty()).map(str::to_lowercase),
 	}
 }
+
+#[test]
+fn test_raw_markdown_comment() {
+	let comment = serde_json::json!({"kind": "t1", "data": {
+		"id": "abc123",
+		"author": "spez",
+		"parent_id": "t3_def456",
+		"body": "*hello* <world>",
+		"body_html": "<div class=\"md\"><p><em>hello</em> &lt;world&gt;</p></div>",
+	}});
+	let build = |cookie: &str| {
+		let req = Request::builder().header("Cookie", cookie).body(Body::empty()).unwrap();
+		build_comment(&comment, &comment["data"], Vec::new(), "/r/rust/comments/def456/x/", "", "", &HashSet::new(), &req)
+	};
+
+	assert_eq!(build("show_raw_markdown=on").body, "<div class=\"md\"><pre>*hello* &lt;world&gt;</pre></div>");
+	assert_eq!(build("show_raw_markdown=off").body, "<div class=\"md\"><p><em>hello</em> &lt;world&gt;</p></div>");
+}
+
+#[test]
+fn test_comment_author_flair() {
+	let comment = serde_json::json!({"kind": "t1", "data": {
+		"id": "abc123",
+		"author": "spez",
+		"parent_id": "t3_def456",
+		"body_html": "",
+		"author_flair_type": "richtext",
+		"author_flair_richtext": [
+			{"e": "emoji", "a": ":snoo:", "u": "https://emoji.redditmedia.com/abc/snoo"},
+			{"e": "text", "t": " Admin"},
+		],
+		"author_flair_text": ":snoo: Admin",
+		"author_flair_background_color": "#ff4500",
+		"author_flair_text_color": "light",
+	}});
+	let req = Request::builder().body(Body::empty()).unwrap();
+	let flair = build_comment(&comment, &comment["data"], Vec::new(), "/r/rust/comments/def456/x/", "", "", &HashSet::new(), &req)
+		.author
+		.flair;
+
+	let parts = flair
+		.flair_parts
+		.iter()
+		.map(|part| (part.flair_part_type.as_str(), part.value.as_str()))
+		.collect::<Vec<_>>();
+	assert_eq!(parts, [("emoji", "/emoji/abc/snoo"), ("text", " Admin")]);
+	assert_eq!(flair.text, ":snoo: Admin");
+	assert_eq!(flair.background_color, "#ff4500");
+	assert_eq!(flair.foreground_color, "white");
+}
+
+#[test]
+fn test_comment_score_hidden() {
+	let build = |score_hidden: bool| {
+		let comment = serde_json::json!({"kind": "t1", "data": {
+			"id": "abc123",
+			"author": "spez",
+			"parent_id": "t3_def456",
+			"body_html": "",
+			"score": 42,
+			"score_hidden": score_hidden,
+		}});
+		let req = Request::builder().body(Body::empty()).unwrap();
+		build_comment(&comment, &comment["data"], Vec::new(), "/r/rust/comments/def456/x/", "", "", &HashSet::new(), &req)
+	};
+
+	let hidden = build(true);
+	assert_eq!(hidden.score, ("\u{2022}".to_string(), "Hidden".to_string()));
+	assert_eq!(hidden.score_value, 42);
+	assert_eq!(build(false).score, ("42".to_string(), "42".to_string()));
+}
+
+#[test]
+fn test_comment_collapse_reason() {
+	let build = |extra: serde_json::Value| {
+		let mut comment = serde_json::json!({"kind": "t1", "data": {
+			"id": "abc123",
+			"author": "spez",
+			"parent_id": "t3_def456",
+			"body_html": "",
+		}});
+		comment["data"].as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+		let req = Request::builder().body(Body::empty()).unwrap();
+		build_comment(&comment, &comment["data"], Vec::new(), "/r/rust/comments/def456/x/", "", "", &HashSet::new(), &req)
+	};
+
+	let crowd_control = build(serde_json::json!({"collapsed": true, "collapsed_because_crowd_control": true}));
+	assert!(crowd_control.collapsed);
+	assert_eq!(crowd_control.collapse_reason.as_deref(), Some("collapsed by crowd control"));
+	let by_code = build(serde_json::json!({"collapsed": true, "collapsed_reason_code": "CROWD_CONTROL"}));
+	assert_eq!(by_code.collapse_reason.as_deref(), Some("collapsed by crowd control"));
+
+	let low_score = build(serde_json::json!({"collapsed": true, "collapsed_reason_code": "LOW_SCORE", "collapsed_reason": "comment score below threshold"}));
+	assert!(low_score.collapsed);
+	assert_eq!(low_score.collapse_reason.as_deref(), Some("collapsed for low score"));
+
+	let open = build(serde_json::json!({"collapsed": false, "collapsed_because_crowd_control": null}));
+	assert!(!open.collapsed);
+	assert_eq!(open.collapse_reason, None);
+}
+
+#[test]
+fn test_resolve_comment_sort() {
+	// User preference > suggested sort > instance default
+	assert_eq!(resolve_comment_sort(Some("new"), Some("qa"), Some("top")), "new");
+	assert_eq!(resolve_comment_sort(None, Some("qa"), Some("top")), "qa");
+	assert_eq!(resolve_comment_sort(None, None, Some("top")), "top");
+	assert_eq!(resolve_comment_sort(None, None, None), "");
+
+	// An empty cookie or instance setting counts as unset
+	assert_eq!(resolve_comment_sort(Some(""), None, Some("top")), "top");
+	assert_eq!(resolve_comment_sort(Some(""), Some(""), Some("")), "");
+}
+
+#[test]
+fn test_sort_comment_tree() {
+	let comment = |id: &str, score: i64, created: f64, replies: Vec<Comment>| Comment {
+		id: id.to_string(),
+		kind: "t1".to_string(),
+		score_value: score,
+		created_unix: created,
+		replies,
+		..Comment::default()
+	};
+	let ids = |comments: &[Comment]| comments.iter().map(|c| c.id.clone()).collect::<Vec<_>>();
+	let tree = || {
+		vec![
+			comment(
+				"old_low",
+				1,
+				100.0,
+				vec![comment("reply_old_high", 9, 150.0, vec![]), comment("reply_new_low", 2, 250.0, vec![])],
+			),
+			comment("pinned", 0, 50.0, vec![]),
+			comment("new_high", 10, 300.0, vec![]),
+			comment("mid", 5, 200.0, vec![]),
+			Comment {
+				kind: "more".to_string(),
+				..comment("more", 0, 0.0, vec![])
+			},
+		]
+		.into_iter()
+		.map(|c| Comment { stickied: c.id == "pinned", ..c })
+		.collect::<Vec<_>>()
+	};
+
+	let mut top = tree();
+	sort_comment_tree(&mut top, "top");
+	assert_eq!(ids(&top), ["pinned", "new_high", "mid", "old_low", "more"]);
+	assert_eq!(ids(&top[3].replies), ["reply_old_high", "reply_new_low"]);
+
+	let mut new = tree();
+	sort_comment_tree(&mut new, "new");
+	assert_eq!(ids(&new), ["pinned", "new_high", "mid", "old_low", "more"]);
+	assert_eq!(ids(&new[3].replies), ["reply_new_low", "reply_old_high"]);
+
+	let mut old = tree();
+	sort_comment_tree(&mut old, "old");
+	assert_eq!(ids(&old), ["pinned", "old_low", "mid", "new_high", "more"]);
+
+	let mut unsorted = tree();
+	sort_comment_tree(&mut unsorted, "confidence");
+	assert_eq!(ids(&unsorted), ids(&tree()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_stream_template_matches_render() {
+	let req = Request::builder().body(Body::empty()).unwrap();
+	let thread = |id: &str, replies: serde_json::Value| {
+		serde_json::json!({"kind": "t1", "data": {
+			"id": id,
+			"author": "spez",
+			"parent_id": "t3_def456",
+			"body_html": format!("<div class=\"md\"><p>comment {id}</p></div>"),
+			"replies": replies,
+		}})
+	};
+	let listing = serde_json::json!({"data": {"children": [
+		thread("one", serde_json::json!({"data": {"children": [thread("two", serde_json::json!(""))]}})),
+		thread("three", serde_json::json!("")),
+	]}});
+	let post = serde_json::json!({"data": {"id": "def456", "title": "Streaming"}});
+	let page = || async {
+		PostTemplate {
+			comments: parse_comments(&listing, "/r/rust/comments/def456/x/", "spez", "", &HashSet::new(), &req),
+			post: parse_post(&post).await,
+			sort: String::new(),
+			prefs: Preferences::new(&req),
+			single_thread: false,
+			url: "/r/rust/comments/def456/x/".to_string(),
+			url_without_query: "/r/rust/comments/def456/x/".to_string(),
+			comment_query: String::new(),
+			host: String::new(),
+			stream_comments: false,
+		}
+	};
+
+	let rendered = page().await.render().unwrap();
+	assert_eq!(count_comments(&page().await.comments), 3);
+	assert!(rendered.contains("comment two"));
+
+	let streamed = hyper::body::to_bytes(stream_template(page().await).into_body()).await.unwrap();
+	assert_eq!(String::from_utf8(streamed.to_vec()).unwrap(), rendered);
+}
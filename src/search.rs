@@ -1,5 +1,8 @@
 // CRATES
-use crate::utils::{self, catch_random, error, filter_posts, format_num, format_url, get_filters, param, redirect, setting, template, val, Post, Preferences};
+use crate::utils::{
+	self, catch_random, error, filter_blocked_authors, filter_low_score_posts, filter_nsfw_posts, filter_posts, filter_posts_by_domain, filter_promoted_posts, format_num,
+	format_url, get_blocked_users, get_domain_filters, get_filters, param, redirect, setting, template, val, Post, Preferences,
+};
 use crate::{
 	client::json,
 	subreddit::{can_access_quarantine, quarantine},
@@ -55,11 +58,8 @@ static REDDIT_URL_MATCH: Lazy<Regex> = Lazy::new(|| Regex::new(r"^https?://([^\.
 // SERVICES
 pub async fn find(req: Request<Body>) -> Result<Response<Body>, String> {
 	// This ensures that during a search, no NSFW posts are fetched at all
-	let nsfw_results = if setting(&req, "show_nsfw") == "on" && !utils::sfw_only() {
-		"&include_over_18=on"
-	} else {
-		""
-	};
+	let show_nsfw = show_nsfw_results(&req);
+	let nsfw_results = if show_nsfw { "&include_over_18=on" } else { "&include_over_18=off" };
 	let path = format!("{}.json?{}{}&raw_json=1", req.uri().path(), req.uri().query().unwrap_or_default(), nsfw_results);
 	let mut query = param(&path, "q").unwrap_or_default();
 	query = REDDIT_URL_MATCH.replace(&query, "").to_string();
@@ -120,7 +120,20 @@ pub async fn find(req: Request<Body>) -> Result<Response<Body>, String> {
 	} else {
 		match Post::fetch(&path, quarantined).await {
 			Ok((mut posts, after)) => {
-				let (_, all_posts_filtered) = filter_posts(&mut posts, &filters);
+				let prefs = Preferences::new(&req);
+				if prefs.hide_promoted == "on" {
+					filter_promoted_posts(&mut posts);
+				}
+				// Reddit doesn't always honor include_over_18, so drop any NSFW results it returns anyway
+				if !show_nsfw {
+					filter_nsfw_posts(&mut posts);
+				}
+				let (_, mut all_posts_filtered) = filter_posts(&mut posts, &filters);
+				all_posts_filtered |= filter_blocked_authors(&mut posts, &get_blocked_users(&req)).1;
+				all_posts_filtered |= filter_posts_by_domain(&mut posts, &get_domain_filters(&req)).1;
+				if let Ok(min) = setting(&req, "min_post_score").parse() {
+					all_posts_filtered |= filter_low_score_posts(&mut posts, min).1;
+				}
 				let no_posts = posts.is_empty();
 				let all_posts_hidden_nsfw = !no_posts && (posts.iter().all(|p| p.flags.nsfw) && setting(&req, "show_nsfw") != "on");
 				Ok(template(&SearchTemplate {
@@ -136,7 +149,7 @@ pub async fn find(req: Request<Body>) -> Result<Response<Body>, String> {
 						restrict_sr: param(&path, "restrict_sr").unwrap_or_default(),
 						typed,
 					},
-					prefs: Preferences::new(&req),
+					prefs,
 					url,
 					is_filtered: false,
 					all_posts_filtered,
@@ -156,6 +169,11 @@ pub async fn find(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 }
 
+// NSFW results are only shown if the user opted in and the instance allows it
+fn show_nsfw_results(req: &Request<Body>) -> bool {
+	setting(req, "show_nsfw") == "on" && !utils::sfw_only()
+}
+
 async fn search_subreddits(q: &str, typed: &str) -> Vec<Subreddit> {
 	let limit = if typed == "sr_user" { "50" } else { "3" };
 	let subreddit_search_path = format!("/subreddits/search.json?q={}&limit={limit}", q.replace(' ', "+"));
@@ -181,3 +199,30 @@ async fn search_subreddits(q: &str, typed: &str) -> Vec<Subreddit> {
 		})
 		.collect::<Vec<Subreddit>>()
 }
+
+#[cfg(test)]
+use sealed_test::prelude::*;
+
+#[test]
+#[sealed_test(env = [("REDLIB_SFW_ONLY", "on")])]
+fn test_search_drops_nsfw_in_sfw_mode() {
+	let req = Request::builder().header("Cookie", "show_nsfw=on").body(Body::empty()).unwrap();
+	assert!(!show_nsfw_results(&req));
+
+	let mut posts = tokio::runtime::Runtime::new().unwrap().block_on(async {
+		vec![
+			utils::parse_post(&serde_json::json!({"data": {"id": "sfw", "over_18": false}})).await,
+			utils::parse_post(&serde_json::json!({"data": {"id": "nsfw", "over_18": true}})).await,
+		]
+	});
+	filter_nsfw_posts(&mut posts);
+	assert_eq!(posts.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["sfw"]);
+}
+
+#[test]
+fn test_search_nsfw_opt_in() {
+	let req = Request::builder().header("Cookie", "show_nsfw=on").body(Body::empty()).unwrap();
+	assert!(show_nsfw_results(&req));
+	let req = Request::builder().body(Body::empty()).unwrap();
+	assert!(!show_nsfw_results(&req));
+}
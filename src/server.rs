@@ -106,6 +106,7 @@ pub trait RequestExt {
 	fn set_params(&mut self, params: Params) -> Option<Params>;
 	fn cookies(&self) -> Vec<Cookie<'_>>;
 	fn cookie(&self, name: &str) -> Option<Cookie<'_>>;
+	fn user_agent(&self) -> &str;
 }
 
 pub trait ResponseExt {
@@ -145,6 +146,10 @@ impl RequestExt for Request<Body> {
 	fn cookie(&self, name: &str) -> Option<Cookie<'_>> {
 		self.cookies().into_iter().find(|c| c.name() == name)
 	}
+
+	fn user_agent(&self) -> &str {
+		self.headers().get("User-Agent").and_then(|header| header.to_str().ok()).unwrap_or_default()
+	}
 }
 
 impl ResponseExt for Response<Body> {
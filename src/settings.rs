@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 
 // CRATES
+use crate::config::get_setting;
 use crate::server::ResponseExt;
-use crate::utils::{redirect, template, Preferences};
+use crate::utils::{nsfw_bypass_duration, nsfw_bypass_token, redirect, sfw_only, template, Preferences};
 use askama::Template;
 use cookie::Cookie;
 use futures_lite::StreamExt;
@@ -14,12 +15,20 @@ use time::{Duration, OffsetDateTime};
 #[template(path = "settings.html")]
 struct SettingsTemplate {
 	prefs: Preferences,
+	/// Instance defaults for preferences, set through `REDLIB_DEFAULT_*`.
+	defaults: HashMap<String, String>,
 	url: String,
 }
 
+impl SettingsTemplate {
+	fn instance_default(&self, name: &str) -> Option<&str> {
+		self.defaults.get(name).map(String::as_str)
+	}
+}
+
 // CONSTANTS
 
-const PREFS: [&str; 15] = [
+const PREFS: [&str; 30] = [
 	"theme",
 	"front_page",
 	"layout",
@@ -35,6 +44,21 @@ const PREFS: [&str; 15] = [
 	"hide_awards",
 	"hide_score",
 	"disable_visit_reddit_confirmation",
+	"sidebar_length",
+	"sort_subscriptions",
+	"show_raw_markdown",
+	"color_authors",
+	"author_hovercards",
+	"min_post_score",
+	"max_title_length",
+	"hide_promoted",
+	"hide_stickied",
+	"constrain_body_images",
+	"time_format",
+	"compact_media",
+	"selfpost_link_previews",
+	"blocked_users",
+	"domain_filters",
 ];
 
 // FUNCTIONS
@@ -44,10 +68,21 @@ pub async fn get(req: Request<Body>) -> Result<Response<Body>, String> {
 	let url = req.uri().to_string();
 	Ok(template(&SettingsTemplate {
 		prefs: Preferences::new(&req),
+		defaults: effective_defaults(),
 		url,
 	}))
 }
 
+/// Returns the instance default of every preference the operator has set
+/// through a `REDLIB_DEFAULT_*` environment variable or config entry, keyed
+/// by preference name.
+pub fn effective_defaults() -> HashMap<String, String> {
+	PREFS
+		.iter()
+		.filter_map(|&name| get_setting(&format!("REDLIB_DEFAULT_{}", name.to_uppercase())).map(|value| (name.to_string(), value)))
+		.collect()
+}
+
 // Set cookies using response "Set-Cookie" header
 pub async fn set(req: Request<Body>) -> Result<Response<Body>, String> {
 	// Split the body into parts
@@ -142,3 +177,42 @@ pub async fn restore(req: Request<Body>) -> Result<Response<Body>, String> {
 pub async fn update(req: Request<Body>) -> Result<Response<Body>, String> {
 	Ok(set_cookies_method(req, false))
 }
+
+// Let the NSFW landing page be bypassed for a while, without touching the show_nsfw preference
+pub async fn bypass_nsfw(req: Request<Body>) -> Result<Response<Body>, String> {
+	let query = req.uri().query().unwrap_or_default().as_bytes();
+	let form = url::form_urlencoded::parse(query).collect::<HashMap<_, _>>();
+
+	// Only redirect within this instance
+	let path = match form.get("redirect") {
+		Some(path) if path.starts_with('/') && !path.starts_with("//") && !path.starts_with("/\\") => path.to_string(),
+		_ => "/".to_string(),
+	};
+
+	let mut response = redirect(&path);
+	if !sfw_only() {
+		let expires = OffsetDateTime::now_utc() + nsfw_bypass_duration();
+		response.insert_cookie(
+			Cookie::build(("nsfw_bypass", nsfw_bypass_token(expires.unix_timestamp())))
+				.path("/")
+				.http_only(true)
+				.expires(expires)
+				.into(),
+		);
+	}
+
+	Ok(response)
+}
+
+#[cfg(test)]
+use sealed_test::prelude::*;
+
+#[test]
+#[sealed_test(env = [("REDLIB_DEFAULT_SHOW_NSFW", "on"), ("REDLIB_DEFAULT_BLUR_NSFW", "on"), ("REDLIB_DEFAULT_LAYOUT", "compact")])]
+fn test_effective_defaults() {
+	let defaults = effective_defaults();
+	assert_eq!(defaults.get("show_nsfw").map(String::as_str), Some("on"));
+	assert_eq!(defaults.get("blur_nsfw").map(String::as_str), Some("on"));
+	assert_eq!(defaults.get("layout").map(String::as_str), Some("compact"));
+	assert!(!defaults.contains_key("theme"));
+}
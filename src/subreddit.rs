@@ -1,11 +1,15 @@
 // CRATES
 use crate::utils::{
-	catch_random, error, filter_posts, format_num, format_url, get_filters, nsfw_landing, param, redirect, rewrite_urls, setting, template, val, Post, Preferences, Subreddit,
+	catch_random, error, filter_blocked_authors, filter_low_score_posts, filter_posts, filter_posts_by_domain, filter_promoted_posts, filter_stickied_posts,
+	filtered_subscriptions, format_num, format_url, get_blocked_users, get_domain_filters, get_filters, json_response, normalize_subreddit, nsfw_landing, param, redirect,
+	rewrite_urls, setting, sfw_only, template, val, Post, Preferences, Subreddit,
 };
 use crate::{client::json, server::ResponseExt, RequestExt};
 use askama::Template;
 use cookie::Cookie;
 use hyper::{Body, Request, Response};
+use route_recognizer::Params;
+use serde_json::Value;
 
 use time::{Duration, OffsetDateTime};
 
@@ -28,6 +32,8 @@ struct SubredditTemplate {
 	/// Whether all posts were hidden because they are NSFW (and user has disabled show NSFW)
 	all_posts_hidden_nsfw: bool,
 	no_posts: bool,
+	/// Subscribed subreddits hidden from the subscription feed by a filter.
+	filtered_subscriptions: Vec<String>,
 }
 
 #[derive(Template)]
@@ -56,8 +62,8 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 	let root = req.uri().path() == "/";
 	let subscribed = setting(&req, "subscriptions");
 	let front_page = setting(&req, "front_page");
-	let post_sort = req.cookie("post_sort").map_or_else(|| "hot".to_string(), |c| c.value().to_string());
-	let sort = req.param("sort").unwrap_or_else(|| req.param("id").unwrap_or(post_sort));
+	let prefs = Preferences::new(&req);
+	let sort = active_sort(&req.params(), &prefs);
 
 	let sub_name = req.param("sub").unwrap_or(if front_page == "default" || front_page.is_empty() {
 		if subscribed.is_empty() {
@@ -80,7 +86,8 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 
 	// Request subreddit metadata
-	let sub = if !sub_name.contains('+') && sub_name != subscribed && sub_name != "popular" && sub_name != "all" {
+	let regular_sub = !sub_name.contains('+') && sub_name != subscribed && sub_name != "popular" && sub_name != "all";
+	let sub = if regular_sub {
 		// Regular subreddit
 		subreddit(&sub_name, quarantined).await.unwrap_or_default()
 	} else if sub_name == subscribed {
@@ -114,6 +121,11 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 	let url = String::from(req.uri().path_and_query().map_or("", |val| val.as_str()));
 	let redirect_url = url[1..].replace('?', "%3F").replace('&', "%26").replace('+', "%2B");
 	let filters = get_filters(&req);
+	let filtered_subscriptions = if sub_name == subscribed {
+		filtered_subscriptions(&prefs.subscriptions, &filters)
+	} else {
+		Vec::new()
+	};
 
 	// If all requested subs are filtered, we don't need to fetch posts.
 	if sub_name.split('+').all(|s| filters.contains(s)) {
@@ -122,18 +134,32 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 			posts: Vec::new(),
 			sort: (sort, param(&path, "t").unwrap_or_default()),
 			ends: (param(&path, "after").unwrap_or_default(), String::new()),
-			prefs: Preferences::new(&req),
+			prefs,
 			url,
 			redirect_url,
 			is_filtered: true,
 			all_posts_filtered: false,
 			all_posts_hidden_nsfw: false,
 			no_posts: false,
+			filtered_subscriptions,
 		}))
 	} else {
 		match Post::fetch(&path, quarantined).await {
 			Ok((mut posts, after)) => {
-				let (_, all_posts_filtered) = filter_posts(&mut posts, &filters);
+				if prefs.hide_promoted == "on" {
+					filter_promoted_posts(&mut posts);
+				}
+				// Stickied posts can be kept on a subreddit's own page while
+				// being hidden from feeds and multireddits.
+				if prefs.hide_stickied == "on" || (prefs.hide_stickied == "feeds" && !regular_sub) {
+					filter_stickied_posts(&mut posts);
+				}
+				let (_, mut all_posts_filtered) = filter_posts(&mut posts, &filters);
+				all_posts_filtered |= filter_blocked_authors(&mut posts, &get_blocked_users(&req)).1;
+				all_posts_filtered |= filter_posts_by_domain(&mut posts, &get_domain_filters(&req)).1;
+				if let Ok(min) = setting(&req, "min_post_score").parse() {
+					all_posts_filtered |= filter_low_score_posts(&mut posts, min).1;
+				}
 				let no_posts = posts.is_empty();
 				let all_posts_hidden_nsfw = !no_posts && (posts.iter().all(|p| p.flags.nsfw) && setting(&req, "show_nsfw") != "on");
 				Ok(template(&SubredditTemplate {
@@ -141,13 +167,14 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 					posts,
 					sort: (sort, param(&path, "t").unwrap_or_default()),
 					ends: (param(&path, "after").unwrap_or_default(), after),
-					prefs: Preferences::new(&req),
+					prefs,
 					url,
 					redirect_url,
 					is_filtered: false,
 					all_posts_filtered,
 					all_posts_hidden_nsfw,
 					no_posts,
+					filtered_subscriptions,
 				}))
 			}
 			Err(msg) => match msg.as_str() {
@@ -160,6 +187,18 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 }
 
+/// Resolves the sort of a listing, so the matching tab is highlighted: the
+/// sort in the URL wins, then the user's (or instance's) default post sort,
+/// then "hot".
+pub fn active_sort(params: &Params, prefs: &Preferences) -> String {
+	params
+		.find("sort")
+		.or_else(|| params.find("id"))
+		.or_else(|| Some(prefs.post_sort.as_str()).filter(|sort| !sort.is_empty()))
+		.unwrap_or("hot")
+		.to_string()
+}
+
 pub fn quarantine(req: &Request<Body>, sub: String, restriction: &str) -> Response<Body> {
 	let wall = WallTemplate {
 		title: format!("r/{sub} is {restriction}"),
@@ -214,6 +253,17 @@ pub async fn subscriptions_filters(req: Request<Body>) -> Result<Response<Body>,
 	let mut sub_list = preferences.subscriptions;
 	let mut filters = preferences.filters;
 
+	// Removals match the saved names case-insensitively, without validating or
+	// looking them up, so entries saved before names were validated (e.g.
+	// two-letter subreddits) can still be removed
+	if action.contains(&"unsubscribe".to_string()) || action.contains(&"unfilter".to_string()) {
+		let list = if action.contains(&"unsubscribe".to_string()) { &mut sub_list } else { &mut filters };
+		for part in sub.split('+').filter(|x| x != &"") {
+			list.retain(|s| s.to_lowercase() != part.to_lowercase());
+		}
+		return Ok(subscriptions_filters_response(&sub, &query, &sub_list, &filters));
+	}
+
 	// Retrieve list of posts for these subreddits to extract display names
 
 	let posts = json(format!("/r/{sub}/hot.json?raw_json=1"), true).await;
@@ -235,6 +285,12 @@ pub async fn subscriptions_filters(req: Request<Body>) -> Result<Response<Body>,
 
 	// Find each subreddit name (separated by '+') in sub parameter
 	for part in sub.split('+').filter(|x| x != &"") {
+		// Skip anything that can't be a subreddit name
+		let Some(name) = (if part.starts_with("u_") { Some(part.to_string()) } else { normalize_subreddit(part) }) else {
+			continue;
+		};
+		let part = name.as_str();
+
 		// Retrieve display name for the subreddit
 		let display;
 		let part = if part.starts_with("u_") {
@@ -261,9 +317,6 @@ pub async fn subscriptions_filters(req: Request<Body>) -> Result<Response<Body>,
 			// Reorder sub names alphabetically
 			sub_list.sort_by_key(|a| a.to_lowercase());
 			filters.sort_by_key(|a| a.to_lowercase());
-		} else if action.contains(&"unsubscribe".to_string()) {
-			// Remove sub name from subscribed list
-			sub_list.retain(|s| s.to_lowercase() != part.to_lowercase());
 		} else if action.contains(&"filter".to_string()) && !filters.contains(&part.to_owned()) {
 			// Add each sub name to the filtered list
 			filters.push(part.to_owned());
@@ -271,12 +324,15 @@ pub async fn subscriptions_filters(req: Request<Body>) -> Result<Response<Body>,
 			// Reorder sub names alphabetically
 			filters.sort_by_key(|a| a.to_lowercase());
 			sub_list.sort_by_key(|a| a.to_lowercase());
-		} else if action.contains(&"unfilter".to_string()) {
-			// Remove sub name from filtered list
-			filters.retain(|s| s.to_lowercase() != part.to_lowercase());
 		}
 	}
 
+	Ok(subscriptions_filters_response(&sub, &query, &sub_list, &filters))
+}
+
+/// Redirects back after a subscription or filter change, saving `sub_list` and
+/// `filters` in their cookies.
+fn subscriptions_filters_response(sub: &str, query: &str, sub_list: &[String], filters: &[String]) -> Response<Body> {
 	// Redirect back to subreddit
 	// check for redirect parameter if unsubscribing/unfiltering from outside sidebar
 	let path = if let Some(redirect_path) = param(&format!("?{query}"), "redirect") {
@@ -311,7 +367,7 @@ pub async fn subscriptions_filters(req: Request<Body>) -> Result<Response<Body>,
 		);
 	}
 
-	Ok(response)
+	response
 }
 
 pub async fn wiki(req: Request<Body>) -> Result<Response<Body>, String> {
@@ -382,6 +438,18 @@ pub async fn sidebar(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 }
 
+// Serve Redlib-normalized subreddit metadata as JSON
+pub async fn about_json(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub_name = req.param("sub").unwrap_or_default();
+	let quarantined = can_access_quarantine(&req, &sub_name);
+
+	match subreddit(&sub_name, quarantined).await {
+		Ok(sub) if sub.nsfw && sfw_only() => error(req, "NSFW content is disabled on this instance").await,
+		Ok(sub) => Ok(json_response(&sub.to_json())),
+		Err(msg) => error(req, &msg).await,
+	}
+}
+
 // pub async fn moderators(sub: &str, quarantined: bool) -> Result<Vec<String>, String> {
 // 	// Retrieve and format the html for the moderators list
 // 	Ok(
@@ -424,26 +492,32 @@ async fn subreddit(sub: &str, quarantined: bool) -> Result<Subreddit, String> {
 	// Send a request to the url
 	let res = json(path, quarantined).await?;
 
+	Ok(parse_subreddit(&res))
+}
+
+/// Parses a subreddit's `about.json` response.
+fn parse_subreddit(res: &Value) -> Subreddit {
 	// Metadata regarding the subreddit
 	let members: i64 = res["data"]["subscribers"].as_u64().unwrap_or_default() as i64;
 	let active: i64 = res["data"]["accounts_active"].as_u64().unwrap_or_default() as i64;
 
 	// Fetch subreddit icon either from the community_icon or icon_img value
 	let community_icon: &str = res["data"]["community_icon"].as_str().unwrap_or_default();
-	let icon = if community_icon.is_empty() { val(&res, "icon_img") } else { community_icon.to_string() };
+	let icon = if community_icon.is_empty() { val(res, "icon_img") } else { community_icon.to_string() };
 
-	Ok(Subreddit {
-		name: val(&res, "display_name"),
-		title: val(&res, "title"),
-		description: val(&res, "public_description"),
-		info: rewrite_urls(&val(&res, "description_html")),
+	Subreddit {
+		name: val(res, "display_name"),
+		title: val(res, "title"),
+		description: val(res, "public_description"),
+		info: rewrite_urls(&val(res, "description_html")),
 		// moderators: moderators_list(sub, quarantined).await.unwrap_or_default(),
 		icon: format_url(&icon),
 		members: format_num(members),
 		active: format_num(active),
 		wiki: res["data"]["wiki_enabled"].as_bool().unwrap_or_default(),
 		nsfw: res["data"]["over18"].as_bool().unwrap_or_default(),
-	})
+		subreddit_type: val(res, "subreddit_type"),
+	}
 }
 
 #[tokio::test(flavor = "multi_thread")]
@@ -451,3 +525,64 @@ async fn test_fetching_subreddit() {
 	let subreddit = subreddit("rust", false).await;
 	assert!(subreddit.is_ok());
 }
+
+#[test]
+fn test_parse_user_profile_subreddit() {
+	let sub = parse_subreddit(&serde_json::json!({"kind": "t5", "data": {
+		"display_name": "u_spez",
+		"title": "spez",
+		"subreddit_type": "user",
+		"subscribers": 1234,
+		"over18": false,
+	}}));
+	assert_eq!(sub.name, "u_spez");
+	assert_eq!(sub.subreddit_type, "user");
+	assert!(sub.is_user_profile());
+
+	let sub = parse_subreddit(&serde_json::json!({"kind": "t5", "data": {"display_name": "rust", "subreddit_type": "public"}}));
+	assert!(!sub.is_user_profile());
+}
+
+#[test]
+fn test_active_sort() {
+	let params = |pairs: &[(&str, &str)]| {
+		let mut params = Params::new();
+		for (key, value) in pairs {
+			params.insert((*key).to_string(), (*value).to_string());
+		}
+		params
+	};
+	let prefs = |post_sort: &str| Preferences {
+		post_sort: post_sort.to_string(),
+		..Preferences::default()
+	};
+
+	// The URL wins over the preference
+	assert_eq!(active_sort(&params(&[("sub", "rust"), ("sort", "top")]), &prefs("new")), "top");
+	assert_eq!(active_sort(&params(&[("id", "rising")]), &prefs("new")), "rising");
+
+	// Then the preference, which includes the instance default
+	assert_eq!(active_sort(&params(&[("sub", "rust")]), &prefs("new")), "new");
+
+	// Then hot
+	assert_eq!(active_sort(&params(&[("sub", "rust")]), &prefs("")), "hot");
+	assert_eq!(active_sort(&Params::new(), &Preferences::default()), "hot");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_unsubscribe_unvalidated_name() {
+	// Two-letter names fail normalize_subreddit but may already be saved
+	let mut req = Request::builder()
+		.uri("/r/de/unsubscribe")
+		.header("Cookie", "subscriptions=de+rust; filters=ab")
+		.body(Body::empty())
+		.unwrap();
+	let mut params = Params::new();
+	params.insert("sub".to_string(), "DE".to_string());
+	req.set_params(params);
+
+	let res = subscriptions_filters(req).await.unwrap();
+	let cookies = res.headers().get_all("Set-Cookie").iter().map(|cookie| cookie.to_str().unwrap()).collect::<Vec<_>>();
+	assert!(cookies.iter().any(|cookie| cookie.starts_with("subscriptions=rust;")));
+	assert!(cookies.iter().any(|cookie| cookie.starts_with("filters=ab;")));
+}
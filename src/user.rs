@@ -1,9 +1,15 @@
 // CRATES
 use crate::client::json;
+use crate::config::get_setting;
 use crate::server::RequestExt;
-use crate::utils::{error, filter_posts, format_url, get_filters, nsfw_landing, param, setting, template, Post, Preferences, User};
+use crate::utils::{
+	error, filter_low_score_posts, filter_posts, filter_promoted_posts, format_url, get_filters, json_response, nsfw_landing, param, setting, sfw_only, template, AuthorSummary,
+	Post, Preferences, User,
+};
 use askama::Template;
+use cached::proc_macro::cached;
 use hyper::{Body, Request, Response};
+use serde_json::Value;
 use time::{macros::format_description, OffsetDateTime};
 
 // STRUCTS
@@ -77,7 +83,14 @@ pub async fn profile(req: Request<Body>) -> Result<Response<Body>, String> {
 		// Request user posts/comments from Reddit
 		match Post::fetch(&path, false).await {
 			Ok((mut posts, after)) => {
-				let (_, all_posts_filtered) = filter_posts(&mut posts, &filters);
+				let prefs = Preferences::new(&req);
+				if prefs.hide_promoted == "on" {
+					filter_promoted_posts(&mut posts);
+				}
+				let (_, mut all_posts_filtered) = filter_posts(&mut posts, &filters);
+				if let Ok(min) = setting(&req, "min_post_score").parse() {
+					all_posts_filtered |= filter_low_score_posts(&mut posts, min).1;
+				}
 				let no_posts = posts.is_empty();
 				let all_posts_hidden_nsfw = !no_posts && (posts.iter().all(|p| p.flags.nsfw) && setting(&req, "show_nsfw") != "on");
 				Ok(template(&UserTemplate {
@@ -86,7 +99,7 @@ pub async fn profile(req: Request<Body>) -> Result<Response<Body>, String> {
 					sort: (sort, param(&path, "t").unwrap_or_default()),
 					ends: (param(&path, "after").unwrap_or_default(), after),
 					listing,
-					prefs: Preferences::new(&req),
+					prefs,
 					url,
 					redirect_url,
 					is_filtered: false,
@@ -101,37 +114,130 @@ pub async fn profile(req: Request<Body>) -> Result<Response<Body>, String> {
 	}
 }
 
+// Serve Redlib-normalized user metadata as JSON
+pub async fn about_json(req: Request<Body>) -> Result<Response<Body>, String> {
+	let username = req.param("name").unwrap_or_default();
+
+	match user(&username).await {
+		Ok(user) if user.nsfw && sfw_only() => error(req, "NSFW content is disabled on this instance").await,
+		Ok(user) => Ok(json_response(&user.to_json())),
+		Err(msg) => error(req, &msg).await,
+	}
+}
+
 // USER
 async fn user(name: &str) -> Result<User, String> {
 	// Build the Reddit JSON API path
 	let path: String = format!("/user/{name}/about.json?raw_json=1");
 
 	// Send a request to the url
-	json(path, false).await.map(|res| {
-		// Grab creation date as unix timestamp
-		let created_unix = res["data"]["created"].as_f64().unwrap_or(0.0).round() as i64;
-		let created = OffsetDateTime::from_unix_timestamp(created_unix).unwrap_or(OffsetDateTime::UNIX_EPOCH);
-
-		// Closure used to parse JSON from Reddit APIs
-		let about = |item| res["data"]["subreddit"][item].as_str().unwrap_or_default().to_string();
-
-		// Parse the JSON output into a User struct
-		User {
-			name: res["data"]["name"].as_str().unwrap_or(name).to_owned(),
-			title: about("title"),
-			icon: format_url(&about("icon_img")),
-			karma: res["data"]["total_karma"].as_i64().unwrap_or(0),
-			created: created.format(format_description!("[month repr:short] [day] '[year repr:last_two]")).unwrap_or_default(),
-			banner: about("banner_img"),
-			description: about("public_description"),
-			nsfw: res["data"]["subreddit"]["over_18"].as_bool().unwrap_or_default(),
-		}
+	json(path, false).await.map(|res| parse_user(name, &res))
+}
+
+// Parse the JSON output of a user's about.json into a User struct
+fn parse_user(name: &str, res: &Value) -> User {
+	// Grab creation date as unix timestamp
+	let created_unix = res["data"]["created"].as_f64().unwrap_or(0.0).round() as i64;
+	let created = OffsetDateTime::from_unix_timestamp(created_unix).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+
+	// Closure used to parse JSON from Reddit APIs
+	let about = |item| res["data"]["subreddit"][item].as_str().unwrap_or_default().to_string();
+
+	let name = res["data"]["name"].as_str().unwrap_or(name).to_owned();
+	let icon = match format_url(&about("icon_img")) {
+		icon if icon.is_empty() => fallback_avatar(&name),
+		icon => icon,
+	};
+
+	User {
+		title: about("title"),
+		icon,
+		karma: res["data"]["total_karma"].as_i64().unwrap_or(0),
+		created: created.format(format_description!("[month repr:short] [day] '[year repr:last_two]")).unwrap_or_default(),
+		banner: about("banner_img"),
+		description: about("public_description"),
+		nsfw: res["data"]["subreddit"]["over_18"].as_bool().unwrap_or_default(),
+		name,
+	}
+}
+
+/// Fetches the details shown in an author's hovercard. Authors tend to
+/// appear on several pages in a row, so summaries are cached for a while.
+#[cached(size = 500, time = 600, key = "String", convert = r#"{ name.to_lowercase() }"#, option = true)]
+pub async fn fetch_author_summary(name: &str) -> Option<AuthorSummary> {
+	let res = json(format!("/user/{name}/about.json?raw_json=1"), false).await.ok()?;
+	parse_author_summary(&res)
+}
+
+// Parse the JSON output of a user's about.json into an AuthorSummary
+fn parse_author_summary(res: &Value) -> Option<AuthorSummary> {
+	let name = res["data"]["name"].as_str()?;
+	let user = parse_user(name, res);
+	Some(AuthorSummary {
+		icon: user.icon,
+		link_karma: res["data"]["link_karma"].as_i64().unwrap_or_default(),
+		comment_karma: res["data"]["comment_karma"].as_i64().unwrap_or_default(),
+		created: user.created,
 	})
 }
 
+/// The avatar for a user without one: REDLIB_FALLBACK_AVATAR if set, otherwise
+/// one of Reddit's default snoo avatars, picked deterministically by name.
+fn fallback_avatar(name: &str) -> String {
+	if let Some(avatar) = get_setting("REDLIB_FALLBACK_AVATAR").filter(|avatar| !avatar.is_empty()) {
+		return format_url(&avatar);
+	}
+	let variant = name.bytes().fold(0u32, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte.into())) % 8;
+	format_url(&format!("https://www.redditstatic.com/avatars/defaults/v2/avatar_default_{variant}.png"))
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_fetching_user() {
 	let user = user("spez").await;
 	assert!(user.is_ok());
 	assert!(user.unwrap().karma > 100);
 }
+
+#[test]
+fn test_fallback_avatar() {
+	let user = |icon: &str| parse_user("spez", &serde_json::json!({"data": {"name": "spez", "subreddit": {"icon_img": icon}}}));
+
+	let fallback = user("").icon;
+	assert!(fallback.starts_with("/static/avatars/defaults/v2/avatar_default_"));
+	assert_eq!(user("").icon, fallback);
+	assert_eq!(
+		user("https://styles.redditmedia.com/t5_abc/styles/profileIcon_x.png").icon,
+		"/style/t5_abc/styles/profileIcon_x.png"
+	);
+}
+
+#[test]
+fn test_parse_author_summary() {
+	let res = serde_json::json!({"kind": "t2", "data": {
+		"name": "spez",
+		"created": 1_118_030_400.0,
+		"link_karma": 170_000,
+		"comment_karma": 755_000,
+		"total_karma": 925_000,
+		"is_suspended": false,
+		"subreddit": {"icon_img": "https://styles.redditmedia.com/t5_abc/styles/profileIcon_x.png"},
+	}});
+
+	let summary = parse_author_summary(&res).unwrap();
+	assert_eq!(summary.icon, "/style/t5_abc/styles/profileIcon_x.png");
+	assert_eq!((summary.link_karma, summary.comment_karma), (170_000, 755_000));
+	assert_eq!(summary.created, "Jun 06 '05");
+
+	// Errors and missing accounts have nothing to summarize
+	assert_eq!(parse_author_summary(&serde_json::json!({"error": 404})), None);
+}
+
+#[cfg(test)]
+use sealed_test::prelude::*;
+
+#[test]
+#[sealed_test(env = [("REDLIB_FALLBACK_AVATAR", "/avatar.png")])]
+fn test_configured_fallback_avatar() {
+	let user = parse_user("spez", &serde_json::json!({"data": {"name": "spez", "subreddit": {"icon_img": ""}}}));
+	assert_eq!(user.icon, "/avatar.png");
+}
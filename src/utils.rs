@@ -4,11 +4,14 @@ use crate::config::get_setting;
 //
 use crate::{client::json, server::RequestExt};
 use askama::Template;
+use base64::{engine::general_purpose, Engine as _};
 use cookie::Cookie;
+use futures::stream::{self, StreamExt};
 use hyper::{Body, Request, Response};
 use log::error;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use ring::{hmac, rand::SystemRandom};
 use rust_embed::RustEmbed;
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
@@ -43,11 +46,48 @@ pub enum ResourceType {
 }
 
 // Post flair with content, background color and foreground color
+#[derive(Default)]
 pub struct Flair {
 	pub flair_parts: Vec<FlairPart>,
 	pub text: String,
 	pub background_color: String,
 	pub foreground_color: String,
+	/// css_class holds the subreddit's CSS classes for the flair, reduced to
+	/// characters that are safe in a class attribute.
+	pub css_class: String,
+}
+
+impl Flair {
+	/// Parses the flair of the author of a post or comment from its `data`.
+	pub fn parse_author(data: &Value) -> Self {
+		let field = |name: &str| data[name].as_str().unwrap_or_default().to_string();
+		Self {
+			flair_parts: FlairPart::parse(
+				data["author_flair_type"].as_str().unwrap_or_default(),
+				data["author_flair_richtext"].as_array(),
+				data["author_flair_text"].as_str(),
+			),
+			text: field("author_flair_text"),
+			background_color: field("author_flair_background_color"),
+			foreground_color: if field("author_flair_text_color") == "dark" {
+				"black".to_string()
+			} else {
+				"white".to_string()
+			},
+			css_class: sanitize_class_names(&field("author_flair_css_class")),
+		}
+	}
+}
+
+/// Reduces a space-separated list of CSS class names to letters, digits,
+/// `-` and `_`, dropping any names left empty.
+pub fn sanitize_class_names(classes: &str) -> String {
+	classes
+		.split_whitespace()
+		.map(|class| class.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_').collect::<String>())
+		.filter(|class| !class.is_empty())
+		.collect::<Vec<String>>()
+		.join(" ")
 }
 
 // Part of flair, either emoji or text
@@ -93,37 +133,136 @@ impl FlairPart {
 	}
 }
 
+#[derive(Default)]
 pub struct Author {
 	pub name: String,
 	pub flair: Flair,
 	pub distinguished: String,
+	/// summary is shown in a hovercard, and only fetched when the user turned
+	/// `author_hovercards` on.
+	pub summary: Option<AuthorSummary>,
+}
+
+/// A few details about an account, as shown in an author hovercard.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuthorSummary {
+	pub icon: String,
+	pub link_karma: i64,
+	pub comment_karma: i64,
+	pub created: String,
+}
+
+impl AuthorSummary {
+	/// Post and comment karma in the short form used elsewhere, e.g. "1.2k".
+	pub fn karma(&self) -> (String, String) {
+		(format_num(self.link_karma).0, format_num(self.comment_karma).0)
+	}
 }
 
 pub struct Poll {
 	pub poll_options: Vec<PollOption>,
 	pub voting_end_timestamp: (String, String),
+	/// voting_end is the Unix time (in seconds) voting ends, if Reddit gave one.
+	pub voting_end: Option<f64>,
 	pub total_vote_count: u64,
+	/// prediction is set if this is a prediction (tournament) rather than a
+	/// plain poll.
+	pub prediction: Option<Prediction>,
+}
+
+/// The state of a prediction post, which Reddit serves as a poll.
+pub struct Prediction {
+	/// status is Reddit's `prediction_status`, e.g. "open" or "resolved".
+	pub status: String,
+	/// resolved_option_id is the winning option, once the prediction is resolved.
+	pub resolved_option_id: Option<u64>,
+}
+
+impl Prediction {
+	fn parse(poll_data: &Value) -> Option<Self> {
+		let status = poll_data["prediction_status"].as_str();
+		if !poll_data["is_prediction"].as_bool().unwrap_or_default() && status.is_none() {
+			return None;
+		}
+
+		Some(Self {
+			status: status.unwrap_or_default().to_string(),
+			resolved_option_id: poll_data["resolved_option_id"].as_str().and_then(|id| id.parse().ok()),
+		})
+	}
 }
 
 impl Poll {
 	pub fn parse(poll_data: &Value) -> Option<Self> {
 		poll_data.as_object()?;
 
-		let total_vote_count = poll_data["total_vote_count"].as_u64()?;
+		let prediction = Prediction::parse(poll_data);
+		// Predictions don't always report a vote count
+		let total_vote_count = match poll_data["total_vote_count"].as_u64() {
+			Some(count) => count,
+			None if prediction.is_some() => 0,
+			None => return None,
+		};
 		// voting_end_timestamp is in the format of milliseconds
-		let voting_end_timestamp = time(poll_data["voting_end_timestamp"].as_f64()? / 1000.0);
+		let voting_end = poll_data["voting_end_timestamp"].as_f64().map(|end| end / 1000.0);
+		let voting_end_timestamp = voting_end.map(time).unwrap_or_default();
 		let poll_options = PollOption::parse(&poll_data["options"])?;
 
 		Some(Self {
 			poll_options,
 			voting_end_timestamp,
+			voting_end,
 			total_vote_count,
+			prediction,
 		})
 	}
 
+	/// Whether `option` is the winning option of a resolved prediction.
+	pub fn is_resolved_option(&self, option: &PollOption) -> bool {
+		self.prediction.as_ref().and_then(|prediction| prediction.resolved_option_id) == Some(option.id)
+	}
+
+	/// Whether voting has ended. Polls without an end time are treated as open.
+	pub fn is_closed(&self) -> bool {
+		self.voting_end.is_some_and(|end| end <= OffsetDateTime::now_utc().unix_timestamp() as f64)
+	}
+
+	/// Describes when voting ends, e.g. "Poll ended 2d ago" or "Poll closes in 3h".
+	pub fn end_phrasing(&self) -> String {
+		let rel_time = self.voting_end_timestamp.0.as_str();
+		match (self.is_closed(), rel_time) {
+			(true, "") => "Poll ended".to_string(),
+			(false, "") => "Poll open".to_string(),
+			(true, _) => format!("Poll ended {rel_time}"),
+			// Ends more than a month out are shown as a date rather than "<n> left"
+			(false, _) => rel_time
+				.strip_suffix(" left")
+				.map_or_else(|| format!("Poll closes {rel_time}"), |rel| format!("Poll closes in {rel}")),
+		}
+	}
+
 	pub fn most_votes(&self) -> u64 {
 		self.poll_options.iter().filter_map(|o| o.vote_count).max().unwrap_or(0)
 	}
+
+	/// Returns each option's rounded share of the votes as a percentage, or
+	/// `None` for options Reddit didn't report a vote count for. Predictions
+	/// don't report a total, so the option counts are summed instead.
+	pub fn option_percentages(&self) -> Vec<Option<u8>> {
+		let total = match self.total_vote_count {
+			0 => self.poll_options.iter().filter_map(|o| o.vote_count).sum(),
+			total => total,
+		};
+		self
+			.poll_options
+			.iter()
+			.map(|option| {
+				option
+					.vote_count
+					.map(|votes| (votes * 100 + total / 2).checked_div(total).map_or(0, |share| share.min(100) as u8))
+			})
+			.collect()
+	}
 }
 
 pub struct PollOption {
@@ -160,17 +299,186 @@ pub struct Flags {
 	pub stickied: bool,
 }
 
-#[derive(Debug)]
+/// The kind of content a post holds, as determined by `Media::parse`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostType {
+	Image,
+	Gif,
+	Video,
+	Gallery,
+	Link,
+	SelfText,
+	Live,
+	/// Media Reddit or the host has since removed.
+	RemovedMedia,
+}
+
+impl PostType {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Self::Image => "image",
+			Self::Gif => "gif",
+			Self::Video => "video",
+			Self::Gallery => "gallery",
+			Self::Link => "link",
+			Self::SelfText => "self",
+			Self::Live => "live",
+			Self::RemovedMedia => "removed_media",
+		}
+	}
+}
+
+impl FromStr for PostType {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"image" => Ok(Self::Image),
+			"gif" => Ok(Self::Gif),
+			"video" => Ok(Self::Video),
+			"gallery" => Ok(Self::Gallery),
+			"link" => Ok(Self::Link),
+			"self" => Ok(Self::SelfText),
+			"live" => Ok(Self::Live),
+			"removed_media" => Ok(Self::RemovedMedia),
+			_ => Err(format!("Unknown post type: {s}")),
+		}
+	}
+}
+
+impl std::fmt::Display for PostType {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+/// Who removed a post, per Reddit's `removed_by_category`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RemovalCategory {
+	#[default]
+	None,
+	Moderator,
+	Deleted,
+	Automod,
+	AntiEvilOps,
+	Reddit,
+	CopyrightTakedown,
+	Author,
+	/// A category this version of Redlib doesn't know about yet.
+	Other(String),
+}
+
+impl RemovalCategory {
+	pub fn parse(data: &Value) -> Self {
+		data["removed_by_category"].as_str().map_or(Self::None, |category| category.parse().unwrap_or_default())
+	}
+
+	/// Says who removed the post, for categories Redlib knows about.
+	pub fn description(&self) -> Option<&'static str> {
+		match self {
+			Self::Moderator => Some("Removed by a moderator"),
+			Self::Automod => Some("Removed by AutoModerator"),
+			Self::AntiEvilOps | Self::Reddit => Some("Removed by Reddit"),
+			Self::CopyrightTakedown => Some("Removed for copyright infringement"),
+			Self::Deleted | Self::Author => Some("Deleted by its author"),
+			Self::None | Self::Other(_) => None,
+		}
+	}
+
+	/// Whether the post was removed by someone other than its author, in
+	/// which case an archive may still have a copy of it.
+	pub fn is_recoverable(&self) -> bool {
+		matches!(self, Self::Moderator | Self::Automod | Self::AntiEvilOps | Self::Reddit)
+	}
+}
+
+impl FromStr for RemovalCategory {
+	type Err = std::convert::Infallible;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"" => Self::None,
+			"moderator" => Self::Moderator,
+			"deleted" => Self::Deleted,
+			"automod_filtered" => Self::Automod,
+			"anti_evil_ops" => Self::AntiEvilOps,
+			"reddit" => Self::Reddit,
+			"copyright_takedown" => Self::CopyrightTakedown,
+			"author" => Self::Author,
+			_ => Self::Other(s.to_string()),
+		})
+	}
+}
+
+/// Placeholder images Reddit and Imgur serve in place of removed media.
+const REMOVED_MEDIA_URLS: [&str; 3] = ["https://i.redd.it/removed.png", "https://i.imgur.com/removed.png", "https://imgur.com/removed.png"];
+
+/// Whether `url` is one of the known removed-media placeholders.
+fn is_removed_media_url(url: &str) -> bool {
+	let url = url.split(['?', '#']).next().unwrap_or_default();
+	REMOVED_MEDIA_URLS.iter().any(|removed| url.eq_ignore_ascii_case(removed))
+}
+
+#[derive(Debug, Default)]
 pub struct Media {
 	pub url: String,
 	pub alt_url: String,
 	pub width: i64,
 	pub height: i64,
 	pub poster: String,
+	/// loop_preview_url is the looping video preview Reddit attaches to some
+	/// image posts. The image itself is the post's primary media.
+	pub loop_preview_url: Option<String>,
+	/// needs_quarantine_ack is true for crossposts whose origin is
+	/// quarantined, as their media may not load without opting in.
+	pub needs_quarantine_ack: bool,
+}
+
+/// A still stand-in for a video player in post listings.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ThumbnailCard {
+	pub poster: String,
+	/// play_badge marks the card as a video, to be overlaid with a play icon.
+	pub play_badge: bool,
 }
 
 impl Media {
-	pub async fn parse(data: &Value) -> (String, Self, Vec<GalleryMedia>) {
+	/// Returns the poster image of this video as a thumbnail card, or `None`
+	/// if Reddit didn't provide a poster.
+	pub fn as_thumbnail_card(&self) -> Option<ThumbnailCard> {
+		(!self.poster.is_empty()).then(|| ThumbnailCard {
+			poster: self.poster.clone(),
+			play_badge: true,
+		})
+	}
+
+	/// Returns the width-to-height ratio of this media, or `None` if Reddit
+	/// didn't give both dimensions (as for bare `i.redd.it` images).
+	pub fn aspect_ratio(&self) -> Option<f64> {
+		(self.width > 0 && self.height > 0).then(|| self.width as f64 / self.height as f64)
+	}
+
+	/// Returns the playable URLs of this video in the order the player should
+	/// try them: the DASH fallback first and HLS second, or the other way
+	/// around if the user prefers HLS.
+	pub fn best_urls(&self, prefs: &Preferences) -> Vec<String> {
+		let mut urls = vec![self.url.clone()];
+		if !self.alt_url.is_empty() {
+			if prefs.use_hls == "on" {
+				urls.insert(0, self.alt_url.clone());
+			} else {
+				urls.push(self.alt_url.clone());
+			}
+		}
+		urls
+	}
+
+	pub async fn parse(data: &Value) -> (PostType, Self, Vec<GalleryMedia>) {
+		// Don't render placeholders for removed media as if they were the media
+		if is_removed_media_url(data["url"].as_str().unwrap_or_default()) {
+			return (PostType::RemovedMedia, Self::default(), Vec::new());
+		}
+
 		let mut gallery = Vec::new();
 
 		// Define the various known places that Reddit might put video URLs.
@@ -178,55 +486,95 @@ impl Media {
 		let secure_media = &data["secure_media"]["reddit_video"];
 		let crosspost_parent_media = &data["crosspost_parent_list"][0]["secure_media"]["reddit_video"];
 
+		// Image posts may carry a video preview, which is only a loop of the
+		// image rather than the post's media
+		let is_image = data["post_hint"].as_str().unwrap_or("") == "image";
+		let loop_preview_url = data_preview["fallback_url"].as_str().filter(|_| is_image).map(format_url);
+
 		// If post is a video, return the video
-		let (post_type, url_val, alt_url_val) = if data_preview["fallback_url"].is_string() {
+		let (post_type, url_val, alt_url_val) = if data_preview["fallback_url"].is_string() && !is_image {
 			(
-				if data_preview["is_gif"].as_bool().unwrap_or(false) { "gif" } else { "video" },
+				if data_preview["is_gif"].as_bool().unwrap_or(false) {
+					PostType::Gif
+				} else {
+					PostType::Video
+				},
 				&data_preview["fallback_url"],
 				Some(&data_preview["hls_url"]),
 			)
 		} else if secure_media["fallback_url"].is_string() {
 			(
-				if secure_media["is_gif"].as_bool().unwrap_or(false) { "gif" } else { "video" },
+				if secure_media["is_gif"].as_bool().unwrap_or(false) {
+					PostType::Gif
+				} else {
+					PostType::Video
+				},
 				&secure_media["fallback_url"],
 				Some(&secure_media["hls_url"]),
 			)
 		} else if crosspost_parent_media["fallback_url"].is_string() {
 			(
-				if crosspost_parent_media["is_gif"].as_bool().unwrap_or(false) { "gif" } else { "video" },
+				if crosspost_parent_media["is_gif"].as_bool().unwrap_or(false) {
+					PostType::Gif
+				} else {
+					PostType::Video
+				},
 				&crosspost_parent_media["fallback_url"],
 				Some(&crosspost_parent_media["hls_url"]),
 			)
-		} else if data["post_hint"].as_str().unwrap_or("") == "image" {
+		} else if let Some(post_type) = direct_video_type(data["url"].as_str().unwrap_or_default()) {
+			// Reddit also serves some videos straight from i.redd.it
+			(post_type, &data["url"], None)
+		} else if is_image {
 			// Handle images, whether GIFs or pics
 			let preview = &data["preview"]["images"][0];
 			let mp4 = &preview["variants"]["mp4"];
 
 			if mp4.is_object() {
 				// Return the mp4 if the media is a gif
-				("gif", &mp4["source"]["url"], None)
+				(PostType::Gif, &mp4["source"]["url"], None)
 			} else {
 				// Return the picture if the media is an image
 				if data["domain"] == "i.redd.it" {
-					("image", &data["url"], None)
+					(PostType::Image, &data["url"], None)
 				} else {
-					("image", &preview["source"]["url"], None)
+					(PostType::Image, &preview["source"]["url"], None)
 				}
 			}
 		} else if data["is_self"].as_bool().unwrap_or_default() {
 			// If type is self, return permalink
-			("self", &data["permalink"], None)
+			(PostType::SelfText, &data["permalink"], None)
 		} else if data["is_gallery"].as_bool().unwrap_or_default() {
 			// If this post contains a gallery of images
 			gallery = GalleryMedia::parse(&data["gallery_data"]["items"], &data["media_metadata"]);
 
-			("gallery", &data["url"], None)
+			// Every image failed processing or was removed
+			if gallery.is_empty() && data["gallery_data"]["items"].as_array().is_some_and(|items| !items.is_empty()) {
+				return (PostType::RemovedMedia, Self::default(), gallery);
+			}
+
+			// A gallery of one image is shown as an image post. The item is
+			// kept for its caption and outbound link.
+			if let [only] = gallery.as_slice() {
+				if !only.processing && !only.url.is_empty() {
+					let media = Self {
+						url: only.url.clone(),
+						width: only.width,
+						height: only.height,
+						poster: only.url.clone(),
+						..Self::default()
+					};
+					return (PostType::Image, media, gallery);
+				}
+			}
+
+			(PostType::Gallery, &data["url"], None)
 		} else if data["is_reddit_media_domain"].as_bool().unwrap_or_default() && data["domain"] == "i.redd.it" {
 			// If this post contains a reddit media (image) URL.
-			("image", &data["url"], None)
+			(PostType::Image, &data["url"], None)
 		} else {
 			// If type can't be determined, return url
-			("link", &data["url"], None)
+			(PostType::Link, &data["url"], None)
 		};
 
 		let source = &data["preview"]["images"][0]["source"];
@@ -234,7 +582,7 @@ impl Media {
 		let alt_url = alt_url_val.map_or(String::new(), |val| format_url(val.as_str().unwrap_or_default()));
 
 		(
-			post_type.to_string(),
+			post_type,
 			Self {
 				url: format_url(url_val.as_str().unwrap_or_default()),
 				alt_url,
@@ -243,18 +591,36 @@ impl Media {
 				width: source["width"].as_i64().unwrap_or_default(),
 				height: source["height"].as_i64().unwrap_or_default(),
 				poster: format_url(source["url"].as_str().unwrap_or_default()),
+				loop_preview_url,
+				needs_quarantine_ack: data["crosspost_parent_list"][0]["quarantine"].as_bool().unwrap_or_default(),
 			},
 			gallery,
 		)
 	}
 }
 
+/// Classifies video files that Reddit serves directly from `i.redd.it`
+/// (rather than `v.redd.it`) by their extension.
+fn direct_video_type(url: &str) -> Option<PostType> {
+	let path = url.strip_prefix("https://i.redd.it/").or_else(|| url.strip_prefix("http://i.redd.it/"))?;
+	let path = path.split(['?', '#']).next().unwrap_or_default().to_ascii_lowercase();
+	if path.ends_with(".mp4") {
+		Some(PostType::Video)
+	} else if path.ends_with(".gifv") {
+		Some(PostType::Gif)
+	} else {
+		None
+	}
+}
+
 pub struct GalleryMedia {
 	pub url: String,
 	pub width: i64,
 	pub height: i64,
 	pub caption: String,
 	pub outbound_url: String,
+	/// processing is true if Reddit hasn't finished processing the image yet.
+	pub processing: bool,
 }
 
 impl GalleryMedia {
@@ -263,12 +629,20 @@ impl GalleryMedia {
 			.as_array()
 			.unwrap_or(&Vec::new())
 			.iter()
-			.map(|item| {
+			.filter_map(|item| {
 				// For each image in gallery
 				let media_id = item["media_id"].as_str().unwrap_or_default();
 				let image = &metadata[media_id]["s"];
 				let image_type = &metadata[media_id]["m"];
 
+				// Skip images Reddit failed to process. Unprocessed images are
+				// kept as placeholders; entries without a status are assumed valid.
+				let processing = match metadata[media_id]["status"].as_str() {
+					None | Some("valid") => false,
+					Some("unprocessed") => true,
+					Some(_) => return None,
+				};
+
 				let url = if image_type == "image/gif" {
 					image["gif"].as_str().unwrap_or_default()
 				} else {
@@ -276,16 +650,41 @@ impl GalleryMedia {
 				};
 
 				// Construct gallery items
-				Self {
+				Some(Self {
 					url: format_url(url),
 					width: image["x"].as_i64().unwrap_or_default(),
 					height: image["y"].as_i64().unwrap_or_default(),
 					caption: item["caption"].as_str().unwrap_or_default().to_string(),
-					outbound_url: item["outbound_url"].as_str().unwrap_or_default().to_string(),
-				}
+					outbound_url: unwrap_outbound_url(item["outbound_url"].as_str().unwrap_or_default()),
+					processing,
+				})
 			})
 			.collect::<Vec<Self>>()
 	}
+
+	/// Whether this image links somewhere outside Reddit.
+	pub fn has_outbound(&self) -> bool {
+		!self.outbound_url.is_empty()
+	}
+}
+
+/// Strips Reddit's `out.reddit.com` click-tracking wrapper from an outbound
+/// link, returning the real destination. Other URLs are returned as-is. Only
+/// `http` and `https` links are kept, since they end up in an `href`: an unsafe
+/// destination falls back to the wrapper, and any other unsafe URL is dropped.
+fn unwrap_outbound_url(url: &str) -> String {
+	let is_web = |url: &Url| matches!(url.scheme(), "http" | "https");
+	let Some(parsed) = Url::parse(url).ok().filter(is_web) else {
+		return String::new();
+	};
+	if parsed.host_str() != Some("out.reddit.com") {
+		return url.to_string();
+	}
+	parsed
+		.query_pairs()
+		.find(|(key, _)| key == "url")
+		.and_then(|(_, destination)| Url::parse(&destination).ok().filter(is_web))
+		.map_or_else(|| url.to_string(), String::from)
 }
 
 // Post containing content, metadata and media
@@ -299,7 +698,7 @@ pub struct Post {
 	pub poll: Option<Poll>,
 	pub score: (String, String),
 	pub upvote_ratio: i64,
-	pub post_type: String,
+	pub post_type: PostType,
 	pub flair: Flair,
 	pub flags: Flags,
 	pub thumbnail: Media,
@@ -307,12 +706,92 @@ pub struct Post {
 	pub domain: String,
 	pub rel_time: String,
 	pub created: String,
+	/// edited holds the relative and absolute time of the last edit, or empty
+	/// strings if the post was never edited.
+	pub edited: (String, String),
 	pub num_duplicates: u64,
+	pub num_crossposts: u64,
 	pub comments: (String, String),
+	/// num_comments_raw is the unformatted comment count behind `comments`.
+	pub num_comments_raw: i64,
 	pub gallery: Vec<GalleryMedia>,
 	pub awards: Awards,
 	pub nsfw: bool,
 	pub ws_url: String,
+	pub archived: bool,
+	pub removal_reason: Option<String>,
+	pub removal_category: RemovalCategory,
+	/// removed is true if the post was removed or deleted by anyone.
+	pub removed: bool,
+	pub flair_id: Option<String>,
+	pub promoted: bool,
+	pub spoiler: bool,
+	pub layout_hint: Option<String>,
+	/// indexable is false if Reddit asks search engines not to index the post.
+	pub indexable: bool,
+	/// crosspost_parent is the post this one was crossposted from, if any.
+	pub crosspost_parent: Option<CrosspostParent>,
+	/// total_awards is the number of awards the post received.
+	pub total_awards: u64,
+	/// link_preview is the preview image Reddit made for the link a self
+	/// post is about, if it has one.
+	pub link_preview: Option<Media>,
+}
+
+/// The post a crosspost was shared from, with just enough to render it as
+/// a nested card.
+pub struct CrosspostParent {
+	pub id: String,
+	pub title: String,
+	pub community: String,
+	pub author: String,
+	pub permalink: String,
+	pub post_type: PostType,
+	pub media: Media,
+}
+
+impl CrosspostParent {
+	pub async fn parse(data: &Value) -> Option<Self> {
+		let mut origin = data["crosspost_parent_list"][0].clone();
+		// Keep it shallow: if the origin is itself a crosspost, don't follow it
+		origin.as_object_mut()?.remove("crosspost_parent_list");
+
+		let (post_type, media, _) = Media::parse(&origin).await;
+		let field = |key: &str| origin[key].as_str().unwrap_or_default().to_string();
+
+		Some(Self {
+			id: field("id"),
+			title: sanitize_title(&field("title")),
+			community: field("subreddit"),
+			author: field("author"),
+			permalink: field("permalink"),
+			post_type,
+			media,
+		})
+	}
+
+	/// The link to the origin post. Falls back to its ID, as Reddit sometimes
+	/// leaves out the permalink of crossposts from removed posts.
+	pub fn link(&self) -> String {
+		if self.permalink.is_empty() {
+			format!("/comments/{}", self.id)
+		} else {
+			self.permalink.clone()
+		}
+	}
+}
+
+/// How many posts of a listing may have their media resolved at once.
+const MEDIA_CONCURRENCY: usize = 8;
+
+/// Parses the media and crosspost origin of every post in a listing
+/// concurrently, returning them in the order of `post_list`.
+async fn resolve_media(post_list: &[Value]) -> Vec<((PostType, Media, Vec<GalleryMedia>), Option<CrosspostParent>)> {
+	stream::iter(post_list).map(resolve_post_media).buffered(MEDIA_CONCURRENCY).collect().await
+}
+
+async fn resolve_post_media(post: &Value) -> ((PostType, Media, Vec<GalleryMedia>), Option<CrosspostParent>) {
+	(Media::parse(&post["data"]).await, CrosspostParent::parse(&post["data"]).await)
 }
 
 impl Post {
@@ -333,18 +812,21 @@ impl Post {
 
 		let mut posts: Vec<Self> = Vec::new();
 
+		// Determine the type of media along with the media URL
+		let resolved = resolve_media(post_list).await;
+
 		// For each post from posts list
-		for post in post_list {
+		for (post, ((post_type, media, gallery), crosspost_parent)) in post_list.iter().zip(resolved) {
 			let data = &post["data"];
 
 			let (rel_time, created) = time(data["created_utc"].as_f64().unwrap_or_default());
 			let score = data["score"].as_i64().unwrap_or_default();
 			let ratio: f64 = data["upvote_ratio"].as_f64().unwrap_or(1.0) * 100.0;
-			let title = val(post, "title");
+			let title = sanitize_title(&val(post, "title"));
 
-			// Determine the type of media along with the media URL
-			let (post_type, media, gallery) = Media::parse(data).await;
-			let awards = Awards::parse(&data["all_awardings"]);
+			let awards = Awards::parse(&data["all_awardings"], &data["gildings"]);
+			let total_awards = data["total_awards_received"].as_u64().unwrap_or_else(|| awards.total_count());
+			let removal_category = RemovalCategory::parse(data);
 
 			// selftext_html is set for text posts when browsing.
 			let mut body = rewrite_urls(&val(post, "selftext_html"));
@@ -359,23 +841,11 @@ impl Post {
 				body,
 				author: Author {
 					name: val(post, "author"),
-					flair: Flair {
-						flair_parts: FlairPart::parse(
-							data["author_flair_type"].as_str().unwrap_or_default(),
-							data["author_flair_richtext"].as_array(),
-							data["author_flair_text"].as_str(),
-						),
-						text: val(post, "link_flair_text"),
-						background_color: val(post, "author_flair_background_color"),
-						foreground_color: val(post, "author_flair_text_color"),
-					},
+					flair: Flair::parse_author(data),
 					distinguished: val(post, "distinguished"),
+					summary: None,
 				},
-				score: if data["hide_score"].as_bool().unwrap_or_default() {
-					("\u{2022}".to_string(), "Hidden".to_string())
-				} else {
-					format_num(score)
-				},
+				score: format_score(score, is_score_hidden(data)),
 				upvote_ratio: ratio as i64,
 				post_type,
 				thumbnail: Media {
@@ -384,6 +854,8 @@ impl Post {
 					width: data["thumbnail_width"].as_i64().unwrap_or_default(),
 					height: data["thumbnail_height"].as_i64().unwrap_or_default(),
 					poster: String::new(),
+					loop_preview_url: None,
+					needs_quarantine_ack: false,
 				},
 				media,
 				domain: val(post, "domain"),
@@ -400,29 +872,166 @@ impl Post {
 					} else {
 						"white".to_string()
 					},
+					css_class: sanitize_class_names(&val(post, "link_flair_css_class")),
 				},
 				flags: Flags {
-					nsfw: data["over_18"].as_bool().unwrap_or_default(),
+					nsfw: is_nsfw(data),
 					stickied: data["stickied"].as_bool().unwrap_or_default() || data["pinned"].as_bool().unwrap_or_default(),
 				},
 				permalink: val(post, "permalink"),
 				poll: Poll::parse(&data["poll_data"]),
 				rel_time,
 				created,
+				edited: data["edited"].as_f64().map_or((String::new(), String::new()), time),
 				num_duplicates: post["data"]["num_duplicates"].as_u64().unwrap_or(0),
+				num_crossposts: data["num_crossposts"].as_u64().unwrap_or(0),
 				comments: format_num(data["num_comments"].as_i64().unwrap_or_default()),
+				num_comments_raw: data["num_comments"].as_i64().unwrap_or_default(),
 				gallery,
 				awards,
-				nsfw: post["data"]["over_18"].as_bool().unwrap_or_default(),
+				nsfw: is_nsfw(data),
 				ws_url: val(post, "websocket_url"),
+				archived: data["archived"].as_bool().unwrap_or_default(),
+				removal_reason: removal_reason(data),
+				removed: is_removed(data, &removal_category),
+				removal_category,
+				flair_id: data["link_flair_template_id"].as_str().filter(|id| !id.is_empty()).map(ToString::to_string),
+				promoted: is_promoted(data),
+				spoiler: data["spoiler"].as_bool().unwrap_or_default(),
+				layout_hint: layout_hint(data),
+				crosspost_parent,
+				total_awards,
+				indexable: data["is_robot_indexable"].as_bool().unwrap_or(true),
+				link_preview: selftext_link_preview(data),
 			});
 		}
 
 		Ok((posts, res["data"]["after"].as_str().unwrap_or_default().to_string()))
 	}
+
+	/// Returns the canonical path to this post's comments, regardless of the
+	/// post type. Unlike `permalink`, this never carries a title slug or any
+	/// trailing segments.
+	pub fn comments_url(&self) -> String {
+		format!("/r/{}/comments/{}", self.community, self.id)
+	}
+
+	/// Returns a shareable link to this post on the instance at `host`, e.g.
+	/// `redlib.example.com` or `https://redlib.example.com/`. Any query string
+	/// on the permalink is dropped, so the link carries no tracking parameters.
+	pub fn share_url(&self, host: &str) -> String {
+		let path = self.permalink.split(['?', '#']).next().unwrap_or_default();
+		format!("{}{path}", instance_origin(host))
+	}
+
+	/// Like [`Post::share_url`], but links to the short `/<id>` form, which
+	/// redirects to the post.
+	pub fn short_share_url(&self, host: &str) -> String {
+		format!("{}/{}", instance_origin(host), self.id)
+	}
+
+	/// Returns the title shortened to at most `max` characters plus an
+	/// ellipsis, breaking at the last word boundary and never inside an HTML
+	/// entity. A `max` of 0 leaves the title untouched.
+	pub fn title_truncated(&self, max: usize) -> String {
+		let title = self.title.as_str();
+		if max == 0 || title.chars().count() <= max {
+			return title.to_string();
+		}
+
+		let mut cut = title.char_indices().nth(max).map_or(title.len(), |(i, _)| i);
+		if let Some(amp) = title[..cut].rfind('&') {
+			let entity = title[amp + 1..].split(';').next().unwrap_or_default();
+			let is_entity = title[amp + 1..].contains(';') && !entity.is_empty() && entity.chars().all(|c| c == '#' || c.is_ascii_alphanumeric());
+			if is_entity && amp + 1 + entity.len() >= cut {
+				cut = amp;
+			}
+		}
+
+		let head = &title[..cut];
+		let head = match head.rfind(char::is_whitespace) {
+			Some(space) if space > 0 && !title[cut..].starts_with(char::is_whitespace) => &head[..space],
+			_ => head,
+		};
+		format!("{}…", head.trim_end())
+	}
+
+	/// Whether this post's media and thumbnail should be blurred in listings:
+	/// always for spoilers, and for NSFW posts if the user asked for it.
+	pub fn should_blur(&self, prefs: &Preferences) -> bool {
+		self.spoiler || (self.flags.nsfw && prefs.blur_nsfw == "on")
+	}
+
+	/// The tooltip of the "Removed" tag: who removed the post and, if Reddit
+	/// exposed one, the moderator's reason.
+	pub fn removal_note(&self) -> String {
+		let note = self.removal_category.description().unwrap_or("This post was removed or deleted");
+		match &self.removal_reason {
+			Some(reason) => format!("{note}: {reason}"),
+			None => note.to_string(),
+		}
+	}
+
+	/// Whether the author edited this post after posting it.
+	pub fn is_edited(&self) -> bool {
+		!self.edited.0.is_empty()
+	}
+
+	/// The prefixed name of the community this was posted to, e.g. "r/rust",
+	/// or "u/spez" for posts to a user profile.
+	pub fn community_display(&self) -> String {
+		community_display(&self.community)
+	}
+
+	/// Whether this is a text post.
+	pub fn is_self(&self) -> bool {
+		self.post_type == PostType::SelfText
+	}
+
+	/// Whether Reddit asked for this gallery to be laid out as a grid rather
+	/// than as a column of images.
+	pub fn is_grid_gallery(&self) -> bool {
+		self.layout_hint.as_deref() == Some("grid")
+	}
+
+	/// Whether this post's content is an image, video, gif or gallery.
+	pub fn is_media(&self) -> bool {
+		matches!(self.post_type, PostType::Image | PostType::Video | PostType::Gif | PostType::Gallery)
+	}
+
+	/// Whether this post's media parsed into something a template can show.
+	/// Always false for posts that aren't media posts.
+	pub fn has_renderable_media(&self) -> bool {
+		match self.post_type {
+			PostType::Image | PostType::Video | PostType::Gif => !self.media.url.is_empty(),
+			PostType::Gallery => !self.gallery.is_empty(),
+			_ => false,
+		}
+	}
+
+	/// Returns a thumbnail card to show in place of the video player in post
+	/// listings, on the compact layout or when the `compact_media`
+	/// preference is on.
+	pub fn thumbnail_card(&self, prefs: &Preferences) -> Option<ThumbnailCard> {
+		if !matches!(self.post_type, PostType::Video | PostType::Gif) || (prefs.layout != "compact" && prefs.compact_media != "on") {
+			return None;
+		}
+		self.media.as_thumbnail_card()
+	}
+
+	/// The robots directive for this post's page, if search engines should
+	/// be told anything.
+	pub fn robots(&self) -> Option<&'static str> {
+		robots_directive(self.indexable, self.nsfw, get_setting("REDLIB_NOINDEX_NSFW").is_some_and(|val| val == "on"))
+	}
+
+	/// The number of times this post has been crossposted, formatted for display.
+	pub fn crossposts(&self) -> (String, String) {
+		format_num(self.num_crossposts.try_into().unwrap_or(i64::MAX))
+	}
 }
 
-#[derive(Template)]
+#[derive(Template, Default)]
 #[template(path = "comment.html")]
 // Comment with content, post, score and data/time that it was posted
 pub struct Comment {
@@ -442,17 +1051,59 @@ pub struct Comment {
 	pub highlighted: bool,
 	pub awards: Awards,
 	pub collapsed: bool,
+	/// collapse_reason explains why Reddit collapsed this comment, if it did.
+	pub collapse_reason: Option<String>,
 	pub is_filtered: bool,
 	pub more_count: i64,
+	/// The numeric score, for sorting. Not for display, as it may be hidden.
+	pub score_value: i64,
+	/// created_unix is the Unix time the comment was posted.
+	pub created_unix: f64,
+	pub stickied: bool,
 	pub prefs: Preferences,
 }
 
+impl Comment {
+	/// Whether this comment was written by the author of the post. Deleted
+	/// authors are never considered the OP.
+	pub fn is_op(&self) -> bool {
+		self.author.name != "[deleted]" && !self.post_author.is_empty() && self.author.name == self.post_author
+	}
+
+	/// Link to the top of the thread this comment belongs to. Any comment id,
+	/// query or anchor already on `post_link` is dropped.
+	pub fn root_post_link(&self) -> String {
+		let path = self.post_link.split(['?', '#']).next().unwrap_or_default();
+		let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+		// Keep everything up to and including the post id and its slug.
+		let end = segments.iter().position(|s| *s == "comments").map_or(segments.len(), |i| (i + 3).min(segments.len()));
+		format!("/{}/", segments[..end].join("/"))
+	}
+
+	/// Link to the comment this one replied to, or to the post for top-level comments.
+	pub fn parent_link(&self) -> String {
+		if self.parent_kind == "t1" {
+			comment_permalink(&self.root_post_link(), &self.parent_id)
+		} else {
+			self.root_post_link()
+		}
+	}
+}
+
+/// Link to the comment `comment_id` under the post at `post_permalink`,
+/// whether or not the permalink ends with a slash.
+pub fn comment_permalink(post_permalink: &str, comment_id: &str) -> String {
+	format!("{}/{}/", post_permalink.trim_end_matches('/'), comment_id.trim_matches('/'))
+}
+
 #[derive(Default, Clone)]
 pub struct Award {
 	pub name: String,
 	pub icon_url: String,
 	pub description: String,
 	pub count: i64,
+	/// coin_price is what one of this award cost, in Reddit coins.
+	pub coin_price: u64,
 }
 
 impl std::fmt::Display for Award {
@@ -461,6 +1112,7 @@ impl std::fmt::Display for Award {
 	}
 }
 
+#[derive(Default)]
 pub struct Awards(pub Vec<Award>);
 
 impl std::ops::Deref for Awards {
@@ -477,27 +1129,81 @@ impl std::fmt::Display for Awards {
 	}
 }
 
+/// Named awards for the legacy `gildings` counts, keyed by gilding id.
+const GILDINGS: [(&str, &str, &str); 3] = [
+	("gid_1", "Silver", "https://www.redditstatic.com/gold/awards/icon/silver_64.png"),
+	("gid_2", "Gold", "https://www.redditstatic.com/gold/awards/icon/gold_64.png"),
+	("gid_3", "Platinum", "https://www.redditstatic.com/gold/awards/icon/platinum_64.png"),
+];
+
 // Convert Reddit awards JSON to Awards struct
 impl Awards {
-	pub fn parse(items: &Value) -> Self {
+	/// Parses `all_awardings`, falling back to the silver/gold/platinum counts
+	/// in `gildings` when there are no awardings. On SFW-only instances,
+	/// awards Reddit flags as NSFW are dropped.
+	pub fn parse(items: &Value, gildings: &Value) -> Self {
+		let sfw_instance = sfw_only();
 		let parsed = items.as_array().unwrap_or(&Vec::new()).iter().fold(Vec::new(), |mut awards, item| {
+			if sfw_instance && item["is_nsfw"].as_bool().unwrap_or_default() {
+				return awards;
+			}
+
 			let name = item["name"].as_str().unwrap_or_default().to_string();
 			let icon_url = format_url(item["resized_icons"][0]["url"].as_str().unwrap_or_default());
 			let description = item["description"].as_str().unwrap_or_default().to_string();
 			let count: i64 = i64::from_str(&item["count"].to_string()).unwrap_or(1);
+			let coin_price = item["coin_price"].as_u64().unwrap_or_default();
 
 			awards.push(Award {
 				name,
 				icon_url,
 				description,
 				count,
+				coin_price,
 			});
 
 			awards
 		});
 
+		if parsed.is_empty() {
+			return Self::parse_gildings(gildings);
+		}
+
 		Self(parsed)
 	}
+
+	/// The number of awards given, counting repeats of the same award.
+	pub fn total_count(&self) -> u64 {
+		self.iter().map(|award| u64::try_from(award.count).unwrap_or_default()).sum()
+	}
+
+	/// The number of coins spent on these awards.
+	pub fn total_coins(&self) -> u64 {
+		self.iter().map(|award| award.coin_price * u64::try_from(award.count).unwrap_or_default()).sum()
+	}
+
+	/// `total_coins`, formatted for display.
+	pub fn coins_spent(&self) -> (String, String) {
+		format_num(i64::try_from(self.total_coins()).unwrap_or(i64::MAX))
+	}
+
+	fn parse_gildings(gildings: &Value) -> Self {
+		Self(
+			GILDINGS
+				.iter()
+				.filter_map(|&(id, name, icon)| {
+					let count = gildings[id].as_i64().filter(|&count| count > 0)?;
+					Some(Award {
+						name: name.to_string(),
+						icon_url: format_url(icon),
+						description: name.to_string(),
+						count,
+						coin_price: 0,
+					})
+				})
+				.collect(),
+		)
+	}
 }
 
 #[derive(Template)]
@@ -529,6 +1235,13 @@ pub struct NSFWLandingTemplate {
 	pub url: String,
 }
 
+impl NSFWLandingTemplate {
+	/// Link that bypasses the gate for a while and then returns to `url`.
+	pub fn bypass_url(&self) -> String {
+		format!("/nsfw_bypass?redirect={}", url::form_urlencoded::byte_serialize(self.url.as_bytes()).collect::<String>())
+	}
+}
+
 #[derive(Default)]
 // User struct containing metadata about user
 pub struct User {
@@ -542,6 +1255,22 @@ pub struct User {
 	pub nsfw: bool,
 }
 
+impl User {
+	/// Redlib-normalized view of the user, as served from `/user/{name}/about.json`.
+	pub fn to_json(&self) -> Value {
+		serde_json::json!({
+			"name": self.name,
+			"title": self.title,
+			"icon": self.icon,
+			"karma": self.karma,
+			"created": self.created,
+			"banner": format_url(&self.banner),
+			"description": self.description,
+			"nsfw": self.nsfw,
+		})
+	}
+}
+
 #[derive(Default)]
 // Subreddit struct containing metadata about community
 pub struct Subreddit {
@@ -555,6 +1284,55 @@ pub struct Subreddit {
 	pub active: (String, String),
 	pub wiki: bool,
 	pub nsfw: bool,
+	/// subreddit_type is Reddit's type for the community, e.g. "public",
+	/// "restricted" or "user" for the `u_` subreddits backing user profiles.
+	pub subreddit_type: String,
+}
+
+static REGEX_HTML_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+impl Subreddit {
+	/// Redlib-normalized view of the subreddit, as served from `/r/{sub}/about.json`.
+	pub fn to_json(&self) -> Value {
+		serde_json::json!({
+			"name": self.name,
+			"title": self.title,
+			"description": self.description,
+			"info": self.info,
+			"icon": self.icon,
+			"members": { "formatted": self.members.0, "exact": self.members.1 },
+			"active": { "formatted": self.active.0, "exact": self.active.1 },
+			"wiki": self.wiki,
+			"nsfw": self.nsfw,
+		})
+	}
+
+	/// Whether this is the `u_` subreddit backing a user profile rather than
+	/// a community.
+	pub fn is_user_profile(&self) -> bool {
+		self.subreddit_type == "user"
+	}
+
+	/// Returns the description with HTML tags stripped, truncated to at most
+	/// `max_chars` characters on a word boundary. A `max_chars` of 0 means
+	/// no truncation. Truncated descriptions end with an ellipsis.
+	pub fn short_description(&self, max_chars: usize) -> String {
+		let stripped = REGEX_HTML_TAG.replace_all(&self.description, "");
+		let text = stripped.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+		if max_chars == 0 || text.chars().count() <= max_chars {
+			return text;
+		}
+
+		let cut: String = text.chars().take(max_chars).collect();
+		// Only back off to the last space if the cut landed mid-word
+		let truncated = match (cut.rfind(' '), text.chars().nth(max_chars)) {
+			(Some(idx), Some(next)) if next != ' ' => &cut[..idx],
+			_ => cut.as_str(),
+		};
+
+		format!("{}…", truncated.trim_end())
+	}
 }
 
 // Parser for query params, used in sorting (eg. /r/rust/?sort=hot)
@@ -571,6 +1349,8 @@ pub struct Params {
 pub struct Preferences {
 	pub available_themes: Vec<String>,
 	pub theme: String,
+	/// theme_locked is true if the instance forces `theme` on every user.
+	pub theme_locked: bool,
 	pub front_page: String,
 	pub layout: String,
 	pub wide: String,
@@ -587,6 +1367,21 @@ pub struct Preferences {
 	pub filters: Vec<String>,
 	pub hide_awards: String,
 	pub hide_score: String,
+	pub sidebar_length: String,
+	pub sort_subscriptions: String,
+	pub show_raw_markdown: String,
+	pub author_hovercards: String,
+	pub color_authors: String,
+	pub min_post_score: String,
+	pub max_title_length: String,
+	pub hide_promoted: String,
+	pub hide_stickied: String,
+	pub constrain_body_images: String,
+	pub time_format: String,
+	pub compact_media: String,
+	pub selfpost_link_previews: String,
+	pub blocked_users: Vec<String>,
+	pub domain_filters: Vec<String>,
 }
 
 #[derive(RustEmbed)]
@@ -594,25 +1389,49 @@ pub struct Preferences {
 #[include = "*.css"]
 pub struct ThemeAssets;
 
+// Read available theme names from embedded css files, sorted by name.
+// Always make the default "system" theme available, first.
+fn theme_names() -> Vec<String> {
+	let mut themes: Vec<String> = ThemeAssets::iter().map(|file| file.trim_end_matches(".css").to_owned()).collect();
+	themes.sort();
+	themes.insert(0, "system".to_string());
+	themes
+}
+
+// The embedded themes can't change at runtime, so only list them once
+static THEMES: Lazy<Vec<String>> = Lazy::new(theme_names);
+
+/// Borrows one of the string fields of `Preferences`.
+type PreferenceField = fn(&mut Preferences) -> &mut String;
+
+/// Debug query flags that can override a preference for a single request,
+/// e.g. `?redlib_raw=1`. Only cosmetic preferences belong here: anything that
+/// gates content (NSFW, filters, quarantine) must never be toggleable this way.
+const DEBUG_FLAGS: [(&str, PreferenceField); 4] = [
+	("redlib_raw", |prefs| &mut prefs.show_raw_markdown),
+	("redlib_hide_awards", |prefs| &mut prefs.hide_awards),
+	("redlib_hide_score", |prefs| &mut prefs.hide_score),
+	("redlib_constrain_images", |prefs| &mut prefs.constrain_body_images),
+];
+
 impl Preferences {
 	// Build preferences from cookies
 	pub fn new(req: &Request<Body>) -> Self {
-		// Read available theme names from embedded css files.
-		// Always make the default "system" theme available.
-		let mut themes = vec!["system".to_string()];
-		for file in ThemeAssets::iter() {
-			let chunks: Vec<&str> = file.as_ref().split(".css").collect();
-			themes.push(chunks[0].to_owned());
-		}
-		Self {
-			available_themes: themes,
-			theme: setting(req, "theme"),
+		// Unknown themes are ignored rather than locking users to a broken one
+		let forced_theme = get_setting("REDLIB_FORCE_THEME").filter(|theme| THEMES.contains(theme));
+		let mut prefs = Self {
+			available_themes: THEMES.clone(),
+			theme: forced_theme.clone().unwrap_or_else(|| setting(req, "theme")),
+			theme_locked: forced_theme.is_some(),
 			front_page: setting(req, "front_page"),
-			layout: setting(req, "layout"),
-			wide: setting(req, "wide"),
+			layout: setting_or_default(req, "layout", default_layout_for_ua(req.user_agent()).to_string()),
+			// The user agent says nothing about the viewport, so first-time visitors
+			// get the narrow layout unless the instance sets REDLIB_DEFAULT_WIDE
+			wide: setting_or_default(req, "wide", "off".to_string()),
 			show_nsfw: setting(req, "show_nsfw"),
 			blur_nsfw: setting(req, "blur_nsfw"),
 			use_hls: setting(req, "use_hls"),
+			// Falls back to REDLIB_DEFAULT_HIDE_HLS_NOTIFICATION so operators can hide it instance-wide
 			hide_hls_notification: setting(req, "hide_hls_notification"),
 			autoplay_videos: setting(req, "autoplay_videos"),
 			fixed_navbar: setting_or_default(req, "fixed_navbar", "on".to_string()),
@@ -623,8 +1442,64 @@ impl Preferences {
 			filters: setting(req, "filters").split('+').map(String::from).filter(|s| !s.is_empty()).collect(),
 			hide_awards: setting(req, "hide_awards"),
 			hide_score: setting(req, "hide_score"),
+			sidebar_length: setting(req, "sidebar_length"),
+			sort_subscriptions: setting(req, "sort_subscriptions"),
+			show_raw_markdown: setting(req, "show_raw_markdown"),
+			author_hovercards: setting(req, "author_hovercards"),
+			color_authors: setting(req, "color_authors"),
+			min_post_score: setting(req, "min_post_score"),
+			max_title_length: setting(req, "max_title_length"),
+			hide_promoted: setting_or_default(req, "hide_promoted", "on".to_string()),
+			hide_stickied: setting(req, "hide_stickied"),
+			constrain_body_images: setting_or_default(req, "constrain_body_images", "on".to_string()),
+			time_format: setting_or_default(req, "time_format", "relative".to_string()),
+			compact_media: setting(req, "compact_media"),
+			selfpost_link_previews: setting(req, "selfpost_link_previews"),
+			blocked_users: setting(req, "blocked_users").split('+').map(String::from).filter(|s| !s.is_empty()).collect(),
+			domain_filters: setting(req, "domain_filters").split('+').map(String::from).filter(|s| !s.is_empty()).collect(),
+		};
+		prefs.apply_debug_flags(req.uri().query().unwrap_or_default());
+		prefs
+	}
+
+	/// Overlays the allowed debug flags in `query` on these preferences.
+	/// A flag of `1` or `on` turns its preference on and `0` or `off` turns it
+	/// off; unknown flags and values are ignored.
+	fn apply_debug_flags(&mut self, query: &str) {
+		for (flag, value) in url::form_urlencoded::parse(query.as_bytes()) {
+			let Some(&(_, pref)) = DEBUG_FLAGS.iter().find(|(known, _)| *known == flag) else {
+				continue;
+			};
+			let value = match value.as_ref() {
+				"1" | "on" => "on",
+				"0" | "off" => "off",
+				_ => continue,
+			};
+			*pref(self) = value.to_string();
 		}
 	}
+
+	/// The maximum length of post titles in listings, or 0 for no limit.
+	pub fn title_limit(&self) -> usize {
+		self.max_title_length.parse().unwrap_or_default()
+	}
+
+	/// Returns a copy of the subscriptions sorted alphabetically, ignoring case.
+	pub fn sorted_subscriptions(&self) -> Vec<String> {
+		let mut subscriptions = self.subscriptions.clone();
+		subscriptions.sort_by_key(|s| s.to_lowercase());
+		subscriptions
+	}
+}
+
+/// Picks a layout for users who haven't chosen one, based on a coarse guess
+/// of the device from its User-Agent: compact on phones, cards elsewhere.
+pub fn default_layout_for_ua(ua: &str) -> &'static str {
+	if ["Mobi", "Android", "iPhone"].iter().any(|hint| ua.contains(hint)) {
+		"compact"
+	} else {
+		"card"
+	}
 }
 
 /// Gets a `HashSet` of filters from the cookie in the given `Request`.
@@ -632,10 +1507,43 @@ pub fn get_filters(req: &Request<Body>) -> HashSet<String> {
 	setting(req, "filters").split('+').map(String::from).filter(|s| !s.is_empty()).collect::<HashSet<String>>()
 }
 
-/// Filters a `Vec<Post>` by the given `HashSet` of filters (each filter being
-/// a subreddit name or a user name). If a `Post`'s subreddit or author is
+/// Gets a `HashSet` of blocked user names from the cookie in the given `Request`.
+pub fn get_blocked_users(req: &Request<Body>) -> HashSet<String> {
+	setting(req, "blocked_users")
+		.split('+')
+		.map(String::from)
+		.filter(|s| !s.is_empty())
+		.collect::<HashSet<String>>()
+}
+
+/// Gets a `HashSet` of filtered link domains from the cookie in the given
+/// `Request`, lowercased and without a leading `www.`.
+pub fn get_domain_filters(req: &Request<Body>) -> HashSet<String> {
+	setting(req, "domain_filters")
+		.split('+')
+		.map(|domain| domain.trim().trim_matches('.').trim_start_matches("www.").to_ascii_lowercase())
+		.filter(|s| !s.is_empty())
+		.collect::<HashSet<String>>()
+}
+
+/// Returns the subscribed subreddits that are also filtered, in subscription
+/// order. Posts from these never show up in the subscription feed, which can
+/// otherwise leave it confusingly empty.
+pub fn filtered_subscriptions(subscriptions: &[String], filters: &HashSet<String>) -> Vec<String> {
+	subscriptions
+		.iter()
+		.filter(|sub| filters.iter().any(|filter| filter.eq_ignore_ascii_case(sub)))
+		.cloned()
+		.collect()
+}
+
+/// Filters a `Vec<Post>` by the given `HashSet` of filters (each filter being
+/// a subreddit name or a user name). If a `Post`'s subreddit or author is
 /// found in the filters, it is removed.
 ///
+/// Filters of the form `flair:<id or text>` remove posts whose link flair
+/// template id or flair text matches.
+///
 /// The first value of the return tuple is the number of posts filtered. The
 /// second return value is `true` if all posts were filtered.
 pub fn filter_posts(posts: &mut Vec<Post>, filters: &HashSet<String>) -> (u64, bool) {
@@ -645,7 +1553,7 @@ pub fn filter_posts(posts: &mut Vec<Post>, filters: &HashSet<String>) -> (u64, b
 	if posts.is_empty() {
 		(0, false)
 	} else {
-		posts.retain(|p| !(filters.contains(&p.community) || filters.contains(&["u_", &p.author.name].concat())));
+		posts.retain(|p| !(filters.contains(&p.community) || is_author_listed(p, filters, "u_") || is_flair_filtered(p, filters)));
 
 		// Get the length of the Vec<Post> after applying the filter.
 		// If lb > la, then at least one post was removed.
@@ -655,6 +1563,216 @@ pub fn filter_posts(posts: &mut Vec<Post>, filters: &HashSet<String>) -> (u64, b
 	}
 }
 
+/// Removes NSFW posts (including crossposts of NSFW posts) from a `Vec<Post>`,
+/// for when the user or instance doesn't allow NSFW content.
+pub fn filter_nsfw_posts(posts: &mut Vec<Post>) {
+	posts.retain(|p| !p.nsfw);
+}
+
+/// Whether the author of `post`, prefixed with `prefix`, is in `names`.
+fn is_author_listed(post: &Post, names: &HashSet<String>, prefix: &str) -> bool {
+	names.contains(&[prefix, &post.author.name].concat())
+}
+
+/// Removes posts by any of the `blocked` users. This is kept apart from
+/// [`filter_posts`] so blocked users can be told apart from filtered feeds.
+/// Returns the number of posts removed and whether every post was removed.
+pub fn filter_blocked_authors(posts: &mut Vec<Post>, blocked: &HashSet<String>) -> (u64, bool) {
+	let lb: u64 = posts.len().try_into().unwrap_or(0);
+
+	if posts.is_empty() {
+		(0, false)
+	} else {
+		posts.retain(|p| !is_author_listed(p, blocked, ""));
+
+		let la: u64 = posts.len().try_into().unwrap_or(0);
+
+		(lb - la, posts.is_empty())
+	}
+}
+
+/// Whether `domain` is one of `domains` or a subdomain of one.
+fn is_domain_listed(domain: &str, domains: &HashSet<String>) -> bool {
+	let domain = domain.to_ascii_lowercase();
+	domains
+		.iter()
+		.any(|listed| domain == *listed || domain.strip_suffix(listed.as_str()).is_some_and(|sub| sub.ends_with('.')))
+}
+
+/// Removes posts linking to any of `domains` or their subdomains, like
+/// paywalled news sites. Returns the number of posts removed and whether every
+/// post was removed, like [`filter_posts`].
+pub fn filter_posts_by_domain(posts: &mut Vec<Post>, domains: &HashSet<String>) -> (u64, bool) {
+	let lb: u64 = posts.len().try_into().unwrap_or(0);
+
+	if posts.is_empty() {
+		(0, false)
+	} else {
+		posts.retain(|p| !is_domain_listed(&p.domain, domains));
+
+		let la: u64 = posts.len().try_into().unwrap_or(0);
+
+		(lb - la, posts.is_empty())
+	}
+}
+
+/// Removes promoted posts from a `Vec<Post>`. This runs before
+/// [`filter_posts`] so that ads never count towards the user's filters.
+pub fn filter_promoted_posts(posts: &mut Vec<Post>) {
+	posts.retain(|p| !p.promoted);
+}
+
+/// Removes stickied (pinned) posts from a `Vec<Post>`, returning how many
+/// were removed.
+pub fn filter_stickied_posts(posts: &mut Vec<Post>) -> u64 {
+	let lb = posts.len();
+	posts.retain(|p| !p.flags.stickied);
+	(lb - posts.len()).try_into().unwrap_or(0)
+}
+
+/// Removes posts scoring below `min` from a `Vec<Post>`. Posts whose score
+/// Reddit hides are kept, since there is nothing to compare.
+///
+/// Returns the number of posts removed and whether all posts were removed,
+/// like [`filter_posts`].
+pub fn filter_low_score_posts(posts: &mut Vec<Post>, min: i64) -> (u64, bool) {
+	let lb: u64 = posts.len().try_into().unwrap_or(0);
+
+	if posts.is_empty() {
+		(0, false)
+	} else {
+		posts.retain(|p| p.score.1.parse::<i64>().map_or(true, |score| score >= min));
+
+		let la: u64 = posts.len().try_into().unwrap_or(0);
+
+		(lb - la, posts.is_empty())
+	}
+}
+
+/// Whether a `flair:` filter matches the post's flair template id, or its
+/// flair text ignoring case.
+fn is_flair_filtered(post: &Post, filters: &HashSet<String>) -> bool {
+	filters
+		.iter()
+		.filter_map(|f| f.strip_prefix("flair:"))
+		.any(|flair| post.flair_id.as_deref() == Some(flair) || (!post.flair.text.is_empty() && post.flair.text.eq_ignore_ascii_case(flair)))
+}
+
+/// Returns true if the post is marked NSFW, or if it is a crosspost whose
+/// origin is. Crossposts into SFW subreddits keep the origin's media, so
+/// the origin's flag has to be considered when gating.
+fn is_nsfw(data: &Value) -> bool {
+	data["over_18"].as_bool().unwrap_or_default() || data["crosspost_parent_list"][0]["over_18"].as_bool().unwrap_or_default()
+}
+
+/// Returns the preview image of a self post, which Reddit generates when the
+/// text is mostly a single link. Other posts render their preview as media.
+fn selftext_link_preview(data: &Value) -> Option<Media> {
+	if !data["is_self"].as_bool().unwrap_or_default() {
+		return None;
+	}
+	let source = &data["preview"]["images"][0]["source"];
+	let url = format_url(source["url"].as_str()?);
+	(!url.is_empty()).then(|| Media {
+		url,
+		width: source["width"].as_i64().unwrap_or_default(),
+		height: source["height"].as_i64().unwrap_or_default(),
+		..Media::default()
+	})
+}
+
+/// Returns true if Reddit injected the post as an ad rather than it being
+/// organic content.
+fn is_promoted(data: &Value) -> bool {
+	data["promoted"].as_bool().unwrap_or_default() || data["is_created_from_ads_ui"].as_bool().unwrap_or_default()
+}
+
+/// Returns Reddit's layout hint for the post's media: its `view_type` if set,
+/// otherwise "gallery" or "single" for galleries depending on how many items
+/// they hold. Other posts have no hint.
+fn layout_hint(data: &Value) -> Option<String> {
+	if let Some(view_type) = data["view_type"].as_str().filter(|view_type| !view_type.is_empty()) {
+		return Some(view_type.to_string());
+	}
+	if !data["is_gallery"].as_bool().unwrap_or_default() {
+		return None;
+	}
+	let items = data["gallery_data"]["items"].as_array().map_or(0, Vec::len);
+	Some(if items > 1 { "gallery" } else { "single" }.to_string())
+}
+
+/// Strips control, zero-width and bidirectional override characters, which
+/// can be used to spoof titles. Line breaks and tabs become spaces, and
+/// zero-width joiners are kept inside emoji sequences such as 👩‍💻.
+pub fn sanitize_title(title: &str) -> String {
+	let chars: Vec<char> = title.chars().collect();
+	let is_emoji = |c: Option<&char>| c.is_some_and(|&c| c as u32 >= 0x1F000 || ('\u{2600}'..='\u{27BF}').contains(&c) || c == '\u{FE0F}');
+
+	chars
+		.iter()
+		.enumerate()
+		.filter_map(|(i, &c)| match c {
+			'\t' | '\n' | '\r' => Some(' '),
+			'\u{200D}' if i > 0 && is_emoji(chars.get(i - 1)) && is_emoji(chars.get(i + 1)) => Some(c),
+			'\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2064}' | '\u{2066}'..='\u{2069}' | '\u{061C}' | '\u{180E}' | '\u{FEFF}' => None,
+			c if c.is_control() => None,
+			c => Some(c),
+		})
+		.collect()
+}
+
+/// Turns an instance host, with or without a scheme or trailing slashes, into
+/// an origin links can be appended to. Hosts without a scheme get `https://`.
+fn instance_origin(host: &str) -> String {
+	let host = host.trim().trim_end_matches('/');
+	if host.starts_with("http://") || host.starts_with("https://") {
+		host.to_string()
+	} else {
+		format!("https://{host}")
+	}
+}
+
+/// Returns the robots directive for a post page: `noindex` if Reddit marked
+/// the post as not indexable, or if it's NSFW and `noindex_nsfw` is set.
+pub fn robots_directive(indexable: bool, nsfw: bool, noindex_nsfw: bool) -> Option<&'static str> {
+	(!indexable || (nsfw && noindex_nsfw)).then_some("noindex")
+}
+
+/// Returns the reason a moderator gave for removing a post, if Reddit exposes one.
+fn removal_reason(data: &Value) -> Option<String> {
+	["mod_reason_title", "removal_reason"]
+		.iter()
+		.filter_map(|key| data[key].as_str())
+		.map(str::trim)
+		.find(|reason| !reason.is_empty())
+		.map(ToString::to_string)
+}
+
+/// Whether a post was removed or deleted. Reddit only sends `removed` to
+/// moderators, so anonymous responses rely on `removed_by_category`.
+fn is_removed(data: &Value, category: &RemovalCategory) -> bool {
+	data["removed"].as_bool().unwrap_or_default() || *category != RemovalCategory::None
+}
+
+/// Builds the body of a post. Posts removed by someone other than their
+/// author link to the archival frontend.
+/// If `show_raw_markdown` is set, the post's markdown source is shown instead
+/// of the HTML Reddit rendered from it.
+pub fn post_body(post: &Value, show_raw_markdown: bool) -> String {
+	if RemovalCategory::parse(&post["data"]).is_recoverable() {
+		let reason = removal_reason(&post["data"]).map(|reason| format!(" ({})", escape_html(&reason))).unwrap_or_default();
+		format!(
+			"<div class=\"md\"><p>[removed]{reason} — <a href=\"https://{}{}\">view removed post</a></p></div>",
+			get_setting("REDLIB_PUSHSHIFT_FRONTEND").unwrap_or_else(|| String::from(crate::config::DEFAULT_PUSHSHIFT_FRONTEND)),
+			val(post, "permalink"),
+		)
+	} else if show_raw_markdown && !val(post, "selftext").is_empty() {
+		raw_markdown(&val(post, "selftext"))
+	} else {
+		rewrite_urls(&val(post, "selftext_html"))
+	}
+}
+
 /// Creates a [`Post`] from a provided JSON.
 pub async fn parse_post(post: &Value) -> Post {
 	// Grab UTC time as unix timestamp
@@ -666,44 +1784,31 @@ pub async fn parse_post(post: &Value) -> Post {
 	// Determine the type of media along with the media URL
 	let (post_type, media, gallery) = Media::parse(&post["data"]).await;
 
-	let awards: Awards = Awards::parse(&post["data"]["all_awardings"]);
+	let awards: Awards = Awards::parse(&post["data"]["all_awardings"], &post["data"]["gildings"]);
+	let total_awards = post["data"]["total_awards_received"].as_u64().unwrap_or_else(|| awards.total_count());
+	let removal_category = RemovalCategory::parse(&post["data"]);
 
 	let permalink = val(post, "permalink");
 
 	let poll = Poll::parse(&post["data"]["poll_data"]);
 
-	let body = if val(post, "removed_by_category") == "moderator" {
-		format!(
-			"<div class=\"md\"><p>[removed] — <a href=\"https://{}{permalink}\">view removed post</a></p></div>",
-			get_setting("REDLIB_PUSHSHIFT_FRONTEND").unwrap_or_else(|| String::from(crate::config::DEFAULT_PUSHSHIFT_FRONTEND)),
-		)
-	} else {
-		rewrite_urls(&val(post, "selftext_html"))
-	};
+	let body = post_body(post, false);
 
 	// Build a post using data parsed from Reddit post API
 	Post {
 		id: val(post, "id"),
-		title: val(post, "title"),
+		title: sanitize_title(&val(post, "title")),
 		community: val(post, "subreddit"),
 		body,
 		author: Author {
 			name: val(post, "author"),
-			flair: Flair {
-				flair_parts: FlairPart::parse(
-					post["data"]["author_flair_type"].as_str().unwrap_or_default(),
-					post["data"]["author_flair_richtext"].as_array(),
-					post["data"]["author_flair_text"].as_str(),
-				),
-				text: val(post, "link_flair_text"),
-				background_color: val(post, "author_flair_background_color"),
-				foreground_color: val(post, "author_flair_text_color"),
-			},
+			flair: Flair::parse_author(&post["data"]),
 			distinguished: val(post, "distinguished"),
+			summary: None,
 		},
 		permalink,
 		poll,
-		score: format_num(score),
+		score: format_score(score, is_score_hidden(&post["data"])),
 		upvote_ratio: ratio as i64,
 		post_type,
 		media,
@@ -713,6 +1818,8 @@ pub async fn parse_post(post: &Value) -> Post {
 			width: post["data"]["thumbnail_width"].as_i64().unwrap_or_default(),
 			height: post["data"]["thumbnail_height"].as_i64().unwrap_or_default(),
 			poster: String::new(),
+			loop_preview_url: None,
+			needs_quarantine_ack: false,
 		},
 		flair: Flair {
 			flair_parts: FlairPart::parse(
@@ -727,20 +1834,36 @@ pub async fn parse_post(post: &Value) -> Post {
 			} else {
 				"white".to_string()
 			},
+			css_class: sanitize_class_names(&val(post, "link_flair_css_class")),
 		},
 		flags: Flags {
-			nsfw: post["data"]["over_18"].as_bool().unwrap_or_default(),
+			nsfw: is_nsfw(&post["data"]),
 			stickied: post["data"]["stickied"].as_bool().unwrap_or_default() || post["data"]["pinned"].as_bool().unwrap_or(false),
 		},
 		domain: val(post, "domain"),
 		rel_time,
 		created,
+		edited: post["data"]["edited"].as_f64().map_or((String::new(), String::new()), time),
 		num_duplicates: post["data"]["num_duplicates"].as_u64().unwrap_or(0),
+		num_crossposts: post["data"]["num_crossposts"].as_u64().unwrap_or(0),
 		comments: format_num(post["data"]["num_comments"].as_i64().unwrap_or_default()),
+		num_comments_raw: post["data"]["num_comments"].as_i64().unwrap_or_default(),
 		gallery,
 		awards,
-		nsfw: post["data"]["over_18"].as_bool().unwrap_or_default(),
+		nsfw: is_nsfw(&post["data"]),
 		ws_url: val(post, "websocket_url"),
+		archived: post["data"]["archived"].as_bool().unwrap_or_default(),
+		removal_reason: removal_reason(&post["data"]),
+		removed: is_removed(&post["data"], &removal_category),
+		removal_category,
+		flair_id: post["data"]["link_flair_template_id"].as_str().filter(|id| !id.is_empty()).map(ToString::to_string),
+		promoted: is_promoted(&post["data"]),
+		spoiler: post["data"]["spoiler"].as_bool().unwrap_or_default(),
+		layout_hint: layout_hint(&post["data"]),
+		crosspost_parent: CrosspostParent::parse(&post["data"]).await,
+		total_awards,
+		indexable: post["data"]["is_robot_indexable"].as_bool().unwrap_or(true),
+		link_preview: selftext_link_preview(&post["data"]),
 	}
 }
 
@@ -748,10 +1871,13 @@ pub async fn parse_post(post: &Value) -> Post {
 // FORMATTING
 //
 
+// Placeholder origin for parsing bare paths as URLs
+const PLACEHOLDER_BASE: &str = "https://redlib.invalid";
+
 // Grab a query parameter from a url
 pub fn param(path: &str, value: &str) -> Option<String> {
 	Some(
-		Url::parse(format!("https://libredd.it/{path}").as_str())
+		Url::parse(format!("{PLACEHOLDER_BASE}/{path}").as_str())
 			.ok()?
 			.query_pairs()
 			.into_owned()
@@ -788,6 +1914,27 @@ pub fn setting_or_default(req: &Request<Body>, name: &str, default: String) -> S
 	}
 }
 
+/// Strips any `/r/` or `r/` prefix from a subreddit name typed by a user and
+/// checks it only contains characters Reddit allows. The original case is
+/// kept, since Reddit preserves the display name's case. Names must be 3 to
+/// 21 characters long, as Reddit requires.
+pub fn normalize_subreddit(input: &str) -> Option<String> {
+	let trimmed = input.trim().trim_end_matches('/');
+	let name = ["/r/", "r/", "/R/", "R/"].iter().find_map(|prefix| trimmed.strip_prefix(prefix)).unwrap_or(trimmed);
+
+	let valid = (3..=21).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+	valid.then(|| name.to_string())
+}
+
+/// Prefixes a subreddit name for display and linking: "r/rust", or "u/spez"
+/// for the `u_spez` subreddit backing a user profile.
+pub fn community_display(name: &str) -> String {
+	match name.strip_prefix("u_") {
+		Some(user) => format!("u/{user}"),
+		None => format!("r/{name}"),
+	}
+}
+
 // Detect and redirect in the event of a random subreddit
 pub async fn catch_random(sub: &str, additional: &str) -> Result<Response<Body>, String> {
 	if sub == "random" || sub == "randnsfw" {
@@ -814,6 +1961,7 @@ static REGEX_URL_EMOJI: Lazy<Regex> = Lazy::new(|| Regex::new(r"https?://emoji\.
 static REGEX_URL_PREVIEW: Lazy<Regex> = Lazy::new(|| Regex::new(r"https?://preview\.redd\.it/(.*)").unwrap());
 static REGEX_URL_EXTERNAL_PREVIEW: Lazy<Regex> = Lazy::new(|| Regex::new(r"https?://external\-preview\.redd\.it/(.*)").unwrap());
 static REGEX_URL_STYLES: Lazy<Regex> = Lazy::new(|| Regex::new(r"https?://styles\.redditmedia\.com/(.*)").unwrap());
+static REGEX_URL_EMOTES: Lazy<Regex> = Lazy::new(|| Regex::new(r"https?://reddit-econ-prod-assets-permanent\.s3\.amazonaws\.com/(.*)").unwrap());
 static REGEX_URL_STATIC_MEDIA: Lazy<Regex> = Lazy::new(|| Regex::new(r"https?://www\.redditstatic\.com/(.*)").unwrap());
 
 // Direct urls to proxy if proxy is enabled
@@ -826,8 +1974,8 @@ pub fn format_url(url: &str) -> String {
 
 			let capture = |regex: &Regex, format: &str, segments: i16| {
 				regex.captures(url).map_or(String::new(), |caps| match segments {
-					1 => [format, &caps[1]].join(""),
-					2 => [format, &caps[1], "/", &caps[2]].join(""),
+					1 => [format, &encode_path_segment(&caps[1])].join(""),
+					2 => [format, &encode_path_segment(&caps[1]), "/", &encode_path_segment(&caps[2])].join(""),
 					_ => String::new(),
 				})
 			};
@@ -863,42 +2011,220 @@ pub fn format_url(url: &str) -> String {
 				"a.thumbs.redditmedia.com" => capture(&REGEX_URL_THUMBS_A, "/thumb/a/", 1),
 				"b.thumbs.redditmedia.com" => capture(&REGEX_URL_THUMBS_B, "/thumb/b/", 1),
 				"emoji.redditmedia.com" => capture(&REGEX_URL_EMOJI, "/emoji/", 2),
+				"preview.redd.it" if !is_signed(&parsed) => unsigned_preview_path(&parsed),
 				"preview.redd.it" => capture(&REGEX_URL_PREVIEW, "/preview/pre/", 1),
 				"external-preview.redd.it" => capture(&REGEX_URL_EXTERNAL_PREVIEW, "/preview/external-pre/", 1),
 				"styles.redditmedia.com" => capture(&REGEX_URL_STYLES, "/style/", 1),
 				"www.redditstatic.com" => capture(&REGEX_URL_STATIC_MEDIA, "/static/", 1),
+				// Subreddit emotes, whose media metadata IDs look like `emote|t5_<id>|<id>`
+				"reddit-econ-prod-assets-permanent.s3.amazonaws.com" => capture(&REGEX_URL_EMOTES, "/emote/", 1),
 				_ => url.to_string(),
 			}
 		})
 	}
 }
 
+/// Whether a Reddit preview URL carries the `s` signature, without which
+/// preview.redd.it refuses to serve it.
+fn is_signed(url: &Url) -> bool {
+	// Links in HTML bodies have their query entity-encoded
+	url.query_pairs().any(|(key, value)| key.trim_start_matches("amp;") == "s" && !value.is_empty())
+}
+
+/// Proxies an unsigned preview.redd.it URL from i.redd.it instead, which
+/// serves the original image without a signature. Newer previews prefix the
+/// image's ID with a slug of the post title, as in `some-title-v0-<id>.jpg`.
+fn unsigned_preview_path(url: &Url) -> String {
+	let file = url.path().trim_start_matches('/');
+	let file = file.rsplit_once("-v0-").map_or(file, |(_, id)| id);
+	format!("/img/{}", encode_path_segment(file))
+}
+
+/// Percent-encodes the characters of a captured URL segment that aren't safe
+/// in a proxy path, such as spaces and non-ASCII characters. Sequences that
+/// are already percent-encoded are kept as they are, while a `%` that doesn't
+/// start one is encoded so it can't be misread.
+fn encode_path_segment(segment: &str) -> String {
+	let bytes = segment.as_bytes();
+	let mut encoded = String::with_capacity(segment.len());
+	for (i, &byte) in bytes.iter().enumerate() {
+		let is_escape = byte == b'%' && bytes.len() > i + 2 && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit();
+		if is_escape || (byte.is_ascii_graphic() && !b"%\"<>^`{|}".contains(&byte)) {
+			encoded.push(char::from(byte));
+		} else {
+			encoded.push_str(&format!("%{byte:02X}"));
+		}
+	}
+	encoded
+}
+
+// AMP links carry tracking parameters and an optional ".amp" suffix that
+// should not survive the rewrite to Redlib.
+static REDDIT_AMP_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r##"href="(https|http|)://amp\.reddit\.com/([^"?#]*?)(\.amp)?(\?[^"#]*)?(#[^"]*)?""##).unwrap());
+
+// Whether a query parameter is only meaningful to Reddit's AMP pages
+fn is_amp_param(pair: &str) -> bool {
+	let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+	key.starts_with("amp") || key == "usqp" || ((key.starts_with("utm_") || key == "outputType") && value == "amp")
+}
+
+// Rewrite AMP links to a clean Redlib path without AMP-specific query params
+fn rewrite_amp_urls(input_text: &str) -> String {
+	REDDIT_AMP_REGEX
+		.replace_all(input_text, |caps: &regex::Captures<'_>| {
+			let query = caps.get(4).map_or("", |q| &q.as_str()[1..]);
+			// Query strings in HTML bodies are usually entity-encoded
+			let separator = if query.contains("&amp;") { "&amp;" } else { "&" };
+			let kept = query.split(separator).filter(|pair| !pair.is_empty() && !is_amp_param(pair)).collect::<Vec<&str>>();
+			let query = if kept.is_empty() { String::new() } else { format!("?{}", kept.join(separator)) };
+
+			format!("href=\"/{}{query}{}\"", &caps[2], caps.get(5).map_or("", |f| f.as_str()))
+		})
+		.to_string()
+}
+
+// Internal links that carry a query string
+static INTERNAL_QUERY_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r##"href="(/[^"?#]*)\?([^"#]*)(#[^"]*)?""##).unwrap());
+
+// Whether a query parameter only serves Reddit's apps and deep links
+fn is_deep_link_param(pair: &str) -> bool {
+	let key = pair.split_once('=').map_or(pair, |(key, _)| key);
+	matches!(key, "rdt" | "correlation_id" | "ref_source")
+}
+
+// Strip app and deep-link query params from internal links, so they stay clean Redlib paths
+fn strip_deep_link_params(input_text: &str) -> String {
+	INTERNAL_QUERY_REGEX
+		.replace_all(input_text, |caps: &regex::Captures<'_>| {
+			let query = &caps[2];
+			// Query strings in HTML bodies are usually entity-encoded
+			let separator = if query.contains("&amp;") { "&amp;" } else { "&" };
+			let kept = query.split(separator).filter(|pair| !pair.is_empty() && !is_deep_link_param(pair)).collect::<Vec<&str>>();
+			let query = if kept.is_empty() { String::new() } else { format!("?{}", kept.join(separator)) };
+
+			format!("href=\"{}{query}{}\"", &caps[1], caps.get(3).map_or("", |f| f.as_str()))
+		})
+		.to_string()
+}
+
 // These are links we want to replace in-body
 static REDDIT_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"href="(https|http|)://(www\.|old\.|np\.|amp\.|new\.|)(reddit\.com|redd\.it)/"#).unwrap());
-static REDDIT_PREVIEW_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"https?://(external-preview|preview)\.redd\.it(.*)[^?]").unwrap());
-static REDDIT_EMOJI_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"https?://(www|).redditstatic\.com/(.*)").unwrap());
+// Media URLs end at the closing quote of the attribute or at whitespace, so
+// that each URL in a body is matched (and rewritten) on its own
+static REDDIT_PREVIEW_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"https?://(external-preview|preview)\.redd\.it[^"\s<>]*[^"\s<>?]"#).unwrap());
+// Post shortlinks on the bare redd.it domain, as opposed to its i. and v. media hosts
+static REDDIT_SHORTLINK_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"href="https?://redd\.it/([a-zA-Z0-9]+)/?""#).unwrap());
+static REDDIT_EMOJI_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"https?://(www|).redditstatic\.com/[^"\s<>]*"#).unwrap());
+
+// A named step of `rewrite_urls`. Each rule returns `None` if it doesn't
+// apply to the text, so callers can tell which rules fired.
+type RewriteRule = (&'static str, fn(&str) -> Option<String>);
+
+// The rules applied by `rewrite_urls`, in order
+static REWRITE_RULES: [RewriteRule; 7] = [
+	// Clean up AMP links before they are treated as regular Reddit links
+	("amp", |text| REDDIT_AMP_REGEX.is_match(text).then(|| rewrite_amp_urls(text))),
+	// Expand post shortlinks, which would otherwise become unresolvable top-level paths
+	("shortlink", |text| {
+		REDDIT_SHORTLINK_REGEX
+			.is_match(text)
+			.then(|| REDDIT_SHORTLINK_REGEX.replace_all(text, r#"href="/comments/$1""#).to_string())
+	}),
+	// Rewrite Reddit links to Redlib
+	("reddit", |text| {
+		REDDIT_REGEX.is_match(text).then(|| REDDIT_REGEX.replace_all(text, r#"href="/"#).to_string())
+	}),
+	("deep_link", |text| {
+		let stripped = strip_deep_link_params(text);
+		(stripped != text).then_some(stripped)
+	}),
+	("emoji", |text| {
+		REDDIT_EMOJI_REGEX
+			.is_match(text)
+			.then(|| REDDIT_EMOJI_REGEX.replace_all(text, |emoji: &regex::Captures| format_url(&emoji[0])).to_string())
+	}),
+	// Remove (html-encoded) "\" from URLs.
+	("backslashes", |text| {
+		(text.contains("%5C") || text.contains("\\_")).then(|| text.replace("%5C", "").replace("\\_", "_"))
+	}),
+	// Rewrite external media previews to Redlib
+	("preview", |text| {
+		REDDIT_PREVIEW_REGEX
+			.is_match(text)
+			.then(|| REDDIT_PREVIEW_REGEX.replace_all(text, |preview: &regex::Captures| format_url(&preview[0])).to_string())
+	}),
+];
 
 // Rewrite Reddit links to Redlib in body of text
 pub fn rewrite_urls(input_text: &str) -> String {
-	let text1 =
-		// Rewrite Reddit links to Redlib
-		REDDIT_REGEX.replace_all(input_text, r#"href="/"#)
-			.to_string();
-	let text1 = REDDIT_EMOJI_REGEX
-		.replace_all(&text1, format_url(REDDIT_EMOJI_REGEX.find(&text1).map(|x| x.as_str()).unwrap_or_default()))
-		.to_string()
-		// Remove (html-encoded) "\" from URLs.
-		.replace("%5C", "")
-		.replace("\\_", "_");
+	rewrite_urls_tracked(input_text).0
+}
 
-	// Rewrite external media previews to Redlib
-	if REDDIT_PREVIEW_REGEX.is_match(&text1) {
-		REDDIT_PREVIEW_REGEX
-			.replace_all(&text1, format_url(REDDIT_PREVIEW_REGEX.find(&text1).map(|x| x.as_str()).unwrap_or_default()))
-			.to_string()
-	} else {
-		text1
+// Like `rewrite_urls`, but also returns the names of the rules that fired
+pub fn rewrite_urls_tracked(input_text: &str) -> (String, Vec<&'static str>) {
+	let mut text = input_text.to_string();
+	let mut fired = Vec::new();
+	for (name, rule) in &REWRITE_RULES {
+		if let Some(rewritten) = rule(&text) {
+			text = rewritten;
+			fired.push(*name);
+		}
 	}
+	(text, fired)
+}
+
+// Reddit's giphy integration leaves "![gif](giphy|<id>)" placeholders in bodies
+static GIPHY_PLACEHOLDER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"!\[gif\]\((giphy\|([A-Za-z0-9]+)(?:\|[a-z_]+)?)\)").unwrap());
+
+// Replace giphy placeholders with the proxied gif from the body's media
+// metadata, or a plain link to Giphy if Reddit didn't provide one
+pub fn render_giphy(body: &str, media_metadata: &Value) -> String {
+	GIPHY_PLACEHOLDER_REGEX
+		.replace_all(body, |caps: &regex::Captures| {
+			let (key, id) = (&caps[1], &caps[2]);
+			match media_metadata[key]["s"]["gif"].as_str() {
+				Some(gif) => format!("<img class=\"giphy\" src=\"{}\" alt=\"gif\" loading=\"lazy\">", escape_html(&format_url(gif))),
+				None => format!("<a href=\"https://giphy.com/gifs/{id}\">https://giphy.com/gifs/{id}</a>"),
+			}
+		})
+		.to_string()
+}
+
+static IMG_TAG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"<img\b([^>]*)>").unwrap());
+static IMG_SIZE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\b(?:width|height)="(\d+)""#).unwrap());
+
+// Images this small (e.g. emoji) are left alone
+const MAX_INLINE_IMAGE_SIZE: u32 = 32;
+
+// Mark proxied images in a body so CSS can keep them from overflowing it
+pub fn constrain_images(body: &str) -> String {
+	IMG_TAG_REGEX
+		.replace_all(body, |caps: &regex::Captures| {
+			let attrs = &caps[1];
+			let proxied = attrs.contains(r#"src="/img/"#) || attrs.contains(r#"src="/preview/"#);
+			let emoji_sized = IMG_SIZE_REGEX
+				.captures_iter(attrs)
+				.any(|size| size[1].parse::<u32>().is_ok_and(|size| size <= MAX_INLINE_IMAGE_SIZE));
+
+			if !proxied || emoji_sized {
+				caps[0].to_string()
+			} else if attrs.contains(r#"class=""#) {
+				format!("<img{}>", attrs.replacen(r#"class=""#, r#"class="constrained "#, 1))
+			} else {
+				format!(r#"<img class="constrained"{attrs}>"#)
+			}
+		})
+		.to_string()
+}
+
+// Escape text so it can be embedded in HTML we build ourselves
+pub fn escape_html(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Display markdown source verbatim, escaping anything that looks like HTML
+pub fn raw_markdown(text: &str) -> String {
+	format!("<div class=\"md\"><pre>{}</pre></div>", escape_html(text))
 }
 
 // Format vote count to a string that will be displayed.
@@ -916,6 +2242,40 @@ pub fn format_num(num: i64) -> (String, String) {
 	(truncated, num.to_string())
 }
 
+/// Whether Reddit hides a post's score, either because it is new or because
+/// the post is in contest mode.
+fn is_score_hidden(data: &Value) -> bool {
+	data["hide_score"].as_bool().unwrap_or_default() || data["contest_mode"].as_bool().unwrap_or_default()
+}
+
+// Format a post or comment score, showing a bullet instead when Reddit hides
+// it (e.g. new posts, or comments in contest mode).
+pub fn format_score(score: i64, hidden: bool) -> (String, String) {
+	if hidden {
+		("\u{2022}".to_string(), "Hidden".to_string())
+	} else {
+		format_num(score)
+	}
+}
+
+/// Returns a CSS color for tinting `name` in comment threads. The same user
+/// always gets the same hue, while deleted accounts and AutoModerator get
+/// fixed neutral grays.
+pub fn author_color(name: &str) -> String {
+	match name {
+		"[deleted]" => return "hsl(0, 0%, 55%)".to_string(),
+		"AutoModerator" => return "hsl(0, 0%, 45%)".to_string(),
+		_ => {}
+	}
+
+	// FNV-1a, so colors don't change between builds or restarts
+	let hash = name
+		.to_lowercase()
+		.bytes()
+		.fold(0x811c_9dc5_u32, |hash, byte| (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193));
+	format!("hsl({}, 60%, 55%)", hash % 360)
+}
+
 // Parse a relative and absolute time from a UNIX timestamp
 pub fn time(created: f64) -> (String, String) {
 	let time = OffsetDateTime::from_unix_timestamp(created.round() as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
@@ -952,6 +2312,17 @@ pub fn time(created: f64) -> (String, String) {
 	)
 }
 
+/// Picks which of the relative and absolute times returned by `time()` to
+/// display, per the `time_format` preference. Returns the displayed time
+/// followed by the one to show on hover.
+pub fn time_display<'a>(rel_time: &'a str, created: &'a str, time_format: &str) -> (&'a str, &'a str) {
+	if time_format == "absolute" {
+		(created, rel_time)
+	} else {
+		(rel_time, created)
+	}
+}
+
 // val() function used to parse JSON from Reddit APIs
 pub fn val(j: &Value, k: &str) -> String {
 	j["data"][k].as_str().unwrap_or_default().to_string()
@@ -969,6 +2340,14 @@ pub fn template(t: &impl Template) -> Response<Body> {
 		.unwrap_or_default()
 }
 
+pub fn json_response(value: &Value) -> Response<Body> {
+	Response::builder()
+		.status(200)
+		.header("content-type", "application/json")
+		.body(value.to_string().into())
+		.unwrap_or_default()
+}
+
 pub fn redirect(path: &str) -> Response<Body> {
 	Response::builder()
 		.status(302)
@@ -1013,11 +2392,50 @@ pub fn should_be_nsfw_gated(req: &Request<Body>, req_url: &str) -> bool {
 	let gate_nsfw = (setting(req, "show_nsfw") != "on") || sfw_instance;
 
 	// Nsfw landing gate should not be bypassed on a sfw only instance,
-	let bypass_gate = !sfw_instance && req_url.contains("&bypass_nsfw_landing");
+	let bypass_gate = !sfw_instance
+		&& (req_url.contains("&bypass_nsfw_landing")
+			|| req
+				.cookie("nsfw_bypass")
+				.is_some_and(|cookie| is_valid_nsfw_bypass_token(cookie.value(), OffsetDateTime::now_utc().unix_timestamp())));
 
 	gate_nsfw && !bypass_gate
 }
 
+// Used when REDLIB_NSFW_BYPASS_DURATION is unset or invalid.
+const DEFAULT_NSFW_BYPASS_MINUTES: i64 = 60;
+
+// Signs NSFW gate bypass tokens. The key is generated per process, so
+// outstanding tokens stop working when the instance restarts.
+static NSFW_BYPASS_KEY: Lazy<hmac::Key> = Lazy::new(|| hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new()).expect("Failed to generate NSFW bypass key"));
+
+/// How long a confirmation on the NSFW landing page bypasses the gate for.
+pub fn nsfw_bypass_duration() -> Duration {
+	let minutes = get_setting("REDLIB_NSFW_BYPASS_DURATION")
+		.and_then(|val| val.parse::<i64>().ok())
+		.filter(|&minutes| minutes > 0)
+		.unwrap_or(DEFAULT_NSFW_BYPASS_MINUTES);
+	Duration::minutes(minutes)
+}
+
+/// Issues a token that bypasses the NSFW gate until `expires` (a Unix
+/// timestamp), in the form `<expires>.<signature>`.
+pub fn nsfw_bypass_token(expires: i64) -> String {
+	let expires = expires.to_string();
+	let tag = hmac::sign(&NSFW_BYPASS_KEY, expires.as_bytes());
+	format!("{expires}.{}", general_purpose::URL_SAFE_NO_PAD.encode(tag.as_ref()))
+}
+
+/// Whether `token` was issued by `nsfw_bypass_token` and is unexpired at `now`.
+pub fn is_valid_nsfw_bypass_token(token: &str, now: i64) -> bool {
+	let Some((expires, tag)) = token.split_once('.') else {
+		return false;
+	};
+	let Ok(tag) = general_purpose::URL_SAFE_NO_PAD.decode(tag) else {
+		return false;
+	};
+	hmac::verify(&NSFW_BYPASS_KEY, expires.as_bytes(), &tag).is_ok() && expires.parse::<i64>().is_ok_and(|expires| expires > now)
+}
+
 /// Renders the landing page for NSFW content when the user has not enabled
 /// "show NSFW posts" in settings.
 pub async fn nsfw_landing(req: Request<Body>, req_url: String) -> Result<Response<Body>, String> {
@@ -1050,7 +2468,14 @@ pub async fn nsfw_landing(req: Request<Body>, req_url: String) -> Result<Respons
 
 #[cfg(test)]
 mod tests {
-	use super::{format_num, format_url, rewrite_urls};
+	use super::{
+		author_color, comment_permalink, constrain_images, default_layout_for_ua, filter_blocked_authors, filter_low_score_posts, filter_posts, filter_posts_by_domain,
+		filter_promoted_posts, filter_stickied_posts, filtered_subscriptions, format_num, format_score, format_url, normalize_subreddit, param, parse_post, post_body,
+		render_giphy, resolve_media, rewrite_urls, rewrite_urls_tracked, robots_directive, sanitize_class_names, sanitize_title, theme_names, time, time_display, Author, Awards,
+		Body, Comment, Duration, GalleryMedia, Media, OffsetDateTime, Poll, PostType, Preferences, RemovalCategory, Request, Subreddit, ThumbnailCard, User, THEMES,
+	};
+	use serde_json::json;
+	use std::collections::HashSet;
 
 	#[test]
 	fn format_num_works() {
@@ -1077,6 +2502,61 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn rewrite_urls_strips_amp_params() {
+		assert_eq!(
+			rewrite_urls(r#"<a href="https://amp.reddit.com/r/rust/comments/abc123/some_post/?utm_source=amp&amp;usqp=mq331AQ&amp;amp_js_v=0.1&amp;context=3">link</a>"#),
+			r#"<a href="/r/rust/comments/abc123/some_post/?context=3">link</a>"#
+		);
+		assert_eq!(
+			rewrite_urls(r##"<a href="https://amp.reddit.com/r/rust/comments/abc123/some_post.amp?utm_source=amp#thing">link</a>"##),
+			r##"<a href="/r/rust/comments/abc123/some_post#thing">link</a>"##
+		);
+		// Non-AMP parameters on non-AMP links are left alone
+		assert_eq!(
+			rewrite_urls(r#"<a href="https://www.reddit.com/r/rust/?utm_source=amp">link</a>"#),
+			r#"<a href="/r/rust/?utm_source=amp">link</a>"#
+		);
+	}
+
+	#[test]
+	fn rewrite_urls_tracks_rules() {
+		assert_eq!(rewrite_urls_tracked("plain text"), ("plain text".to_string(), vec![]));
+
+		let (text, fired) = rewrite_urls_tracked(
+			r#"<a href="https://amp.reddit.com/r/rust/?usqp=1">amp</a> <a href="https://www.reddit.com/r/linux%5C_gaming/">link</a> <img src="https://preview.redd.it/abc.png?width=640&amp;s=123">"#,
+		);
+		assert_eq!(fired, ["amp", "reddit", "backslashes", "preview"]);
+		assert_eq!(
+			text,
+			r#"<a href="/r/rust/">amp</a> <a href="/r/linux_gaming/">link</a> <img src="/preview/pre/abc.png?width=640&amp;s=123">"#
+		);
+	}
+
+	#[test]
+	fn rewrite_urls_strips_deep_link_params() {
+		assert_eq!(
+			rewrite_urls(
+				r#"<a href="https://www.reddit.com/r/rust/comments/abc123/title/?rdt=51234&amp;correlation_id=x-1&amp;context=3&amp;ref_source=share#c">post</a> <a href="/r/rust/?rdt=1">sub</a>"#
+			),
+			r#"<a href="/r/rust/comments/abc123/title/?context=3#c">post</a> <a href="/r/rust/">sub</a>"#
+		);
+	}
+
+	#[test]
+	fn rewrite_urls_rewrites_each_media_url() {
+		assert_eq!(
+			rewrite_urls(
+				r#"<p><img src="https://www.redditstatic.com/marketplace-assets/v1/core/emotes/snoomoji_emotes/free_emotes_pack/shrug.gif" width="20" height="20"> and <img src="https://www.redditstatic.com/marketplace-assets/v1/core/emotes/snoomoji_emotes/free_emotes_pack/facepalm.gif" width="20" height="20"></p>"#
+			),
+			r#"<p><img src="/static/marketplace-assets/v1/core/emotes/snoomoji_emotes/free_emotes_pack/shrug.gif" width="20" height="20"> and <img src="/static/marketplace-assets/v1/core/emotes/snoomoji_emotes/free_emotes_pack/facepalm.gif" width="20" height="20"></p>"#
+		);
+		assert_eq!(
+			rewrite_urls(r#"<a href="https://preview.redd.it/first.png?s=1">one</a> <a href="https://external-preview.redd.it/second.jpg?s=2">two</a>"#),
+			r#"<a href="/preview/pre/first.png?s=1">one</a> <a href="/preview/external-pre/second.jpg?s=2">two</a>"#
+		);
+	}
+
 	#[test]
 	fn rewrite_urls_keeps_intentional_backslashes() {
 		assert_eq!(
@@ -1111,12 +2591,1200 @@ mod tests {
 			"/static/marketplace-assets/v1/core/emotes/snoomoji_emotes/free_emotes_pack/shrug.gif"
 		);
 
+		// Preview signatures are kept, and unsigned previews fall back to i.redd.it
+		assert_eq!(format_url("https://preview.redd.it/abc.jpg?width=640&s=sig"), "/preview/pre/abc.jpg?width=640&s=sig");
+		assert_eq!(format_url("https://preview.redd.it/abc.jpg"), "/img/abc.jpg");
+		assert_eq!(format_url("https://preview.redd.it/abc.jpg?width=640&format=pjpg"), "/img/abc.jpg");
+		assert_eq!(format_url("https://preview.redd.it/my-cat-v0-abc123.png?width=640&format=png"), "/img/abc123.png");
+		assert_eq!(
+			format_url("https://preview.redd.it/my-cat-v0-abc123.png?width=640&format=png&s=sig"),
+			"/preview/pre/my-cat-v0-abc123.png?width=640&format=png&s=sig"
+		);
+
+		assert_eq!(
+			format_url("https://reddit-econ-prod-assets-permanent.s3.amazonaws.com/asset-manager/t5_2qh1i/abc123.png"),
+			"/emote/asset-manager/t5_2qh1i/abc123.png"
+		);
+
+		assert_eq!(format_url("https://i.redd.it/foo%20bar.jpg"), "/img/foo%20bar.jpg");
+		assert_eq!(format_url("https://i.redd.it/foo bar.jpg"), "/img/foo%20bar.jpg");
+		assert_eq!(format_url("https://i.redd.it/100%.jpg"), "/img/100%25.jpg");
+		assert_eq!(format_url("https://emoji.redditmedia.com/a%2Fb/c d"), "/emoji/a%2Fb/c%20d");
+
 		assert_eq!(format_url(""), "");
 		assert_eq!(format_url("self"), "");
 		assert_eq!(format_url("default"), "");
 		assert_eq!(format_url("nsfw"), "");
 		assert_eq!(format_url("spoiler"), "");
 	}
+
+	#[test]
+	fn test_short_description() {
+		let sub = Subreddit {
+			description: "A <b>friendly</b> community\n for discussing   the Rust language".to_string(),
+			..Subreddit::default()
+		};
+		// 0 means unlimited, but HTML and extra whitespace are still stripped
+		assert_eq!(sub.short_description(0), "A friendly community for discussing the Rust language");
+		assert_eq!(sub.short_description(1000), "A friendly community for discussing the Rust language");
+		// Truncation backs off to the previous word boundary
+		assert_eq!(sub.short_description(14), "A friendly…");
+		// A cut that lands exactly on a word boundary keeps the whole word
+		assert_eq!(sub.short_description(10), "A friendly…");
+		// A single word longer than the limit is cut mid-word
+		let long = Subreddit {
+			description: "Supercalifragilistic".to_string(),
+			..Subreddit::default()
+		};
+		assert_eq!(long.short_description(5), "Super…");
+	}
+
+	#[test]
+	fn test_sorted_subscriptions() {
+		let prefs = Preferences {
+			subscriptions: vec!["rust".to_string(), "AskReddit".to_string(), "linux".to_string(), "Apple".to_string()],
+			..Preferences::default()
+		};
+		assert_eq!(prefs.sorted_subscriptions(), vec!["Apple", "AskReddit", "linux", "rust"]);
+		// The original insertion order is left untouched
+		assert_eq!(prefs.subscriptions, vec!["rust", "AskReddit", "linux", "Apple"]);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_archived() {
+		let archived = parse_post(&json!({"data": {"id": "abc123", "archived": true}})).await;
+		assert!(archived.archived);
+
+		let current = parse_post(&json!({"data": {"id": "def456"}})).await;
+		assert!(!current.archived);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_crosspost_nsfw_origin() {
+		let crosspost = parse_post(&json!({"data": {
+			"id": "abc123",
+			"subreddit": "pics",
+			"over_18": false,
+			"crosspost_parent_list": [{"id": "def456", "subreddit": "nsfw_pics", "over_18": true}],
+		}}))
+		.await;
+		assert!(crosspost.nsfw);
+		assert!(crosspost.flags.nsfw);
+
+		let plain = parse_post(&json!({"data": {"id": "abc123", "subreddit": "pics", "over_18": false}})).await;
+		assert!(!plain.nsfw);
+	}
+
+	#[test]
+	fn test_post_body_raw_markdown() {
+		let post = json!({"data": {
+			"selftext": "Some **bold** <text>",
+			"selftext_html": "<div class=\"md\"><p>Some <strong>bold</strong> &lt;text&gt;</p></div>",
+		}});
+		assert_eq!(post_body(&post, false), "<div class=\"md\"><p>Some <strong>bold</strong> &lt;text&gt;</p></div>");
+		assert_eq!(post_body(&post, true), "<div class=\"md\"><pre>Some **bold** &lt;text&gt;</pre></div>");
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_removal_reason() {
+		let removed = json!({"data": {
+			"id": "abc123",
+			"permalink": "/r/rust/comments/abc123/title/",
+			"removed_by_category": "moderator",
+			"mod_reason_title": "Rule 3: <No> memes",
+		}});
+		let post = parse_post(&removed).await;
+		assert_eq!(post.removal_reason.as_deref(), Some("Rule 3: <No> memes"));
+		assert_eq!(post.removal_note(), "Removed by a moderator: Rule 3: <No> memes");
+		assert!(post.body.starts_with("<div class=\"md\"><p>[removed] (Rule 3: &lt;No&gt; memes) — "));
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "removal_reason": null}})).await;
+		assert_eq!(post.removal_reason, None);
+		assert_eq!(post.removal_note(), "This post was removed or deleted");
+	}
+
+	#[test]
+	fn test_removal_category() {
+		assert_eq!("moderator".parse(), Ok(RemovalCategory::Moderator));
+		assert_eq!("automod_filtered".parse(), Ok(RemovalCategory::Automod));
+		assert_eq!("anti_evil_ops".parse(), Ok(RemovalCategory::AntiEvilOps));
+		assert_eq!("copyright_takedown".parse(), Ok(RemovalCategory::CopyrightTakedown));
+		assert_eq!("deleted".parse(), Ok(RemovalCategory::Deleted));
+		assert_eq!("community_ops".parse(), Ok(RemovalCategory::Other("community_ops".to_string())));
+		assert_eq!(RemovalCategory::parse(&json!({"removed_by_category": null})), RemovalCategory::None);
+
+		assert!(RemovalCategory::Moderator.is_recoverable());
+		assert!(RemovalCategory::Reddit.is_recoverable());
+		assert!(!RemovalCategory::Author.is_recoverable());
+		assert!(!RemovalCategory::Other("community_ops".to_string()).is_recoverable());
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_removal_category() {
+		let post = parse_post(&json!({"data": {"id": "abc123", "removed_by_category": "automod_filtered", "selftext_html": "<p>[removed]</p>"}})).await;
+		assert_eq!(post.removal_category, RemovalCategory::Automod);
+		assert!(post.body.contains("view removed post"));
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "removed_by_category": "deleted", "selftext_html": "<p>[deleted]</p>"}})).await;
+		assert_eq!(post.removal_category, RemovalCategory::Deleted);
+		assert_eq!(post.body, "<p>[deleted]</p>");
+		assert!(post.removed);
+		assert_eq!(post.removal_note(), "Deleted by its author");
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_removed() {
+		// Moderator-only fields are ignored apart from the removed flag
+		let post = parse_post(&json!({"data": {
+			"id": "abc123",
+			"removed": true,
+			"num_reports": 3,
+			"mod_reports": [["spam", "some_mod"]],
+			"user_reports": [["Breaks r/rust rules", 2]],
+			"approved_by": null,
+		}}))
+		.await;
+		assert!(post.removed);
+		assert_eq!(post.removal_category, RemovalCategory::None);
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "removed_by_category": "moderator"}})).await;
+		assert!(post.removed);
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "removed": false, "num_reports": null}})).await;
+		assert!(!post.removed);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_contest_mode_score_hidden() {
+		let post = parse_post(&json!({"data": {"id": "abc123", "score": 1234, "contest_mode": true}})).await;
+		assert_eq!(post.score, ("\u{2022}".to_string(), "Hidden".to_string()));
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "score": 1234, "hide_score": true, "contest_mode": false}})).await;
+		assert_eq!(post.score, ("\u{2022}".to_string(), "Hidden".to_string()));
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "score": 1234, "contest_mode": false}})).await;
+		assert_eq!(post.score, ("1.2k".to_string(), "1234".to_string()));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_post_edited() {
+		let post = parse_post(&json!({"data": {"id": "abc123", "created_utc": 1_700_000_000.0, "edited": 1_700_003_600.0}})).await;
+		assert!(post.is_edited());
+		assert_eq!(post.edited, time(1_700_003_600.0));
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "created_utc": 1_700_000_000.0, "edited": false}})).await;
+		assert!(!post.is_edited());
+		assert_eq!(post.edited, (String::new(), String::new()));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_num_comments_raw() {
+		let post = parse_post(&json!({"data": {"id": "abc123", "num_comments": 12_345}})).await;
+		assert_eq!(post.num_comments_raw, 12_345);
+		assert_eq!(post.comments, ("12.3k".to_string(), "12345".to_string()));
+
+		let post = parse_post(&json!({"data": {"id": "abc123"}})).await;
+		assert_eq!(post.num_comments_raw, 0);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_media_aspect_ratio() {
+		let media = Media {
+			width: 1920,
+			height: 1080,
+			..Media::default()
+		};
+		assert_eq!(media.aspect_ratio(), Some(1920.0 / 1080.0));
+		assert_eq!(Media { height: 0, ..media }.aspect_ratio(), None);
+		assert_eq!(Media::default().aspect_ratio(), None);
+
+		// Reddit media domain images come without a preview to take dimensions from
+		let (_, media, _) = Media::parse(&json!({"domain": "i.redd.it", "url": "https://i.redd.it/abc123.png", "is_reddit_media_domain": true})).await;
+		assert_eq!((media.width, media.height), (0, 0));
+		assert_eq!(media.aspect_ratio(), None);
+	}
+
+	#[test]
+	fn test_media_best_urls() {
+		let media = Media {
+			url: "/vid/abc123/DASH_720.mp4".to_string(),
+			alt_url: "/hls/abc123/HLSPlaylist.m3u8".to_string(),
+			..Media::default()
+		};
+		let mut prefs = Preferences::default();
+		assert_eq!(media.best_urls(&prefs), ["/vid/abc123/DASH_720.mp4", "/hls/abc123/HLSPlaylist.m3u8"]);
+
+		prefs.use_hls = "on".to_string();
+		assert_eq!(media.best_urls(&prefs), ["/hls/abc123/HLSPlaylist.m3u8", "/vid/abc123/DASH_720.mp4"]);
+
+		let media = Media { alt_url: String::new(), ..media };
+		assert_eq!(media.best_urls(&prefs), ["/vid/abc123/DASH_720.mp4"]);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_thumbnail_card() {
+		let video = parse_post(&json!({"data": {
+			"id": "abc123",
+			"secure_media": {"reddit_video": {"fallback_url": "https://v.redd.it/abc/DASH_720.mp4?source=fallback", "is_gif": false}},
+			"preview": {"images": [{"source": {"url": "https://preview.redd.it/abc.jpg?s=def", "width": 640, "height": 360}}]},
+		}}))
+		.await;
+		let card = Preferences {
+			layout: "card".to_string(),
+			..Default::default()
+		};
+		let compact_media = Preferences {
+			layout: "card".to_string(),
+			compact_media: "on".to_string(),
+			..Default::default()
+		};
+		let compact = Preferences {
+			layout: "compact".to_string(),
+			..Default::default()
+		};
+
+		assert_eq!(video.thumbnail_card(&card), None);
+		let expected = Some(ThumbnailCard {
+			poster: "/preview/pre/abc.jpg?s=def".to_string(),
+			play_badge: true,
+		});
+		assert_eq!(video.thumbnail_card(&compact_media), expected);
+		assert_eq!(video.thumbnail_card(&compact), expected);
+
+		let image = parse_post(&json!({"data": {"id": "abc123", "post_hint": "image", "domain": "i.redd.it", "url": "https://i.redd.it/abc.jpg"}})).await;
+		assert_eq!(image.thumbnail_card(&compact_media), None);
+	}
+
+	#[test]
+	fn test_filtered_subscriptions() {
+		let subscriptions = ["rust", "linux", "Programming"].map(String::from);
+		let filters = HashSet::from(["programming", "u_spez", "flair:Meme"].map(String::from));
+		assert_eq!(filtered_subscriptions(&subscriptions, &filters), ["Programming"]);
+		assert!(filtered_subscriptions(&subscriptions, &HashSet::new()).is_empty());
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_robots_directive() {
+		assert_eq!(robots_directive(true, false, false), None);
+		assert_eq!(robots_directive(false, false, false), Some("noindex"));
+		assert_eq!(robots_directive(true, true, false), None);
+		assert_eq!(robots_directive(true, true, true), Some("noindex"));
+		assert_eq!(robots_directive(true, false, true), None);
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "is_robot_indexable": false}})).await;
+		assert!(!post.indexable);
+		let post = parse_post(&json!({"data": {"id": "abc123"}})).await;
+		assert!(post.indexable);
+	}
+
+	#[test]
+	fn test_gallery_outbound_url() {
+		let items = json!([
+			{"media_id": "a", "caption": "Shop", "outbound_url": "https://out.reddit.com/t3_abc123?url=https%3A%2F%2Fexample.com%2Fshop%3Fid%3D1&token=AQAA&app_name=web2x"},
+			{"media_id": "b", "outbound_url": "https://example.org/page"},
+			{"media_id": "c"},
+			{"media_id": "d", "outbound_url": "https://out.reddit.com/t3_abc123?url=javascript%3Aalert(1)&token=AQAA"},
+			{"media_id": "e", "outbound_url": "javascript:alert(1)"}
+		]);
+		let gallery = GalleryMedia::parse(&items, &json!({}));
+		assert_eq!(gallery[0].outbound_url, "https://example.com/shop?id=1");
+		assert!(gallery[0].has_outbound());
+		assert_eq!(gallery[1].outbound_url, "https://example.org/page");
+		assert!(!gallery[2].has_outbound());
+		assert_eq!(gallery[3].outbound_url, "https://out.reddit.com/t3_abc123?url=javascript%3Aalert(1)&token=AQAA");
+		assert!(!gallery[4].has_outbound());
+	}
+
+	#[test]
+	fn test_default_layout_for_ua() {
+		let iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1";
+		let android = "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Mobile Safari/537.36";
+		let desktop = "Mozilla/5.0 (X11; Linux x86_64; rv:125.0) Gecko/20100101 Firefox/125.0";
+		assert_eq!(default_layout_for_ua(iphone), "compact");
+		assert_eq!(default_layout_for_ua(android), "compact");
+		assert_eq!(default_layout_for_ua(desktop), "card");
+		assert_eq!(default_layout_for_ua(""), "card");
+	}
+
+	#[test]
+	fn test_comment_root_and_parent_links() {
+		let deep = Comment {
+			id: "kx3".to_string(),
+			kind: "t1".to_string(),
+			parent_id: "kx2".to_string(),
+			parent_kind: "t1".to_string(),
+			post_link: "/r/rust/comments/abc123/some_title/".to_string(),
+			..Comment::default()
+		};
+		assert_eq!(deep.root_post_link(), "/r/rust/comments/abc123/some_title/");
+		assert_eq!(deep.parent_link(), "/r/rust/comments/abc123/some_title/kx2/");
+
+		let anchored = Comment {
+			post_link: "/r/rust/comments/abc123/some_title/kx1/?context=3#kx1".to_string(),
+			..deep
+		};
+		assert_eq!(anchored.root_post_link(), "/r/rust/comments/abc123/some_title/");
+		assert_ne!(anchored.root_post_link(), anchored.parent_link());
+
+		let top_level = Comment {
+			parent_id: "abc123".to_string(),
+			parent_kind: "t3".to_string(),
+			..anchored
+		};
+		assert_eq!(top_level.parent_link(), top_level.root_post_link());
+	}
+
+	#[test]
+	fn test_comment_permalink() {
+		assert_eq!(comment_permalink("/r/rust/comments/abc123/some_title/", "kx1"), "/r/rust/comments/abc123/some_title/kx1/");
+		assert_eq!(comment_permalink("/r/rust/comments/abc123/some_title", "kx1"), "/r/rust/comments/abc123/some_title/kx1/");
+		assert_eq!(
+			comment_permalink("/r/rust/comments/abc123/some_title//", "/kx1/"),
+			"/r/rust/comments/abc123/some_title/kx1/"
+		);
+	}
+
+	#[test]
+	fn test_comment_is_op() {
+		let comment = |author: &str, post_author: &str| Comment {
+			author: Author {
+				name: author.to_string(),
+				..Author::default()
+			},
+			post_author: post_author.to_string(),
+			..Comment::default()
+		};
+		assert!(comment("spez", "spez").is_op());
+		assert!(!comment("kn0thing", "spez").is_op());
+		assert!(!comment("[deleted]", "[deleted]").is_op());
+	}
+
+	#[test]
+	fn test_render_giphy() {
+		let body = "<div class=\"md\"><p>![gif](giphy|xT9IgG50Fb7Mi0prBC|downsized)</p></div>";
+		let metadata = json!({
+			"giphy|xT9IgG50Fb7Mi0prBC|downsized": {
+				"e": "AnimatedImage",
+				"s": {"gif": "https://external-preview.redd.it/abc.gif?width=200&s=123"}
+			}
+		});
+		assert_eq!(
+			render_giphy(body, &metadata),
+			"<div class=\"md\"><p><img class=\"giphy\" src=\"/preview/external-pre/abc.gif?width=200&amp;s=123\" alt=\"gif\" loading=\"lazy\"></p></div>"
+		);
+		assert_eq!(
+			render_giphy(body, &json!({})),
+			"<div class=\"md\"><p><a href=\"https://giphy.com/gifs/xT9IgG50Fb7Mi0prBC\">https://giphy.com/gifs/xT9IgG50Fb7Mi0prBC</a></p></div>"
+		);
+	}
+
+	#[test]
+	fn test_subreddit_to_json() {
+		let sub = Subreddit {
+			name: "rust".to_string(),
+			icon: format_url("https://styles.redditmedia.com/t5_2s7lj/styles/communityIcon_abc.png?width=256&s=123"),
+			members: format_num(312_345),
+			..Subreddit::default()
+		};
+		let json = sub.to_json();
+		assert_eq!(json["name"], "rust");
+		assert_eq!(json["icon"], "/style/t5_2s7lj/styles/communityIcon_abc.png?width=256&s=123");
+		assert_eq!(json["members"]["formatted"], "312.3k");
+		assert_eq!(json["members"]["exact"], "312345");
+	}
+
+	#[test]
+	fn test_user_to_json() {
+		let user = User {
+			name: "spez".to_string(),
+			icon: format_url("https://styles.redditmedia.com/t5_3k30p/styles/profileIcon_abc.png?width=256&s=123"),
+			banner: "https://styles.redditmedia.com/t5_3k30p/styles/profileBanner_abc.png".to_string(),
+			karma: 12345,
+			..User::default()
+		};
+		let json = user.to_json();
+		assert_eq!(json["icon"], "/style/t5_3k30p/styles/profileIcon_abc.png?width=256&s=123");
+		assert_eq!(json["banner"], "/style/t5_3k30p/styles/profileBanner_abc.png");
+		assert_eq!(json["karma"], 12345);
+	}
+
+	#[test]
+	fn test_format_score() {
+		assert_eq!(format_score(1234, false), ("1.2k".to_string(), "1234".to_string()));
+		assert_eq!(format_score(-5, false), ("-5".to_string(), "-5".to_string()));
+		assert_eq!(format_score(1234, true), ("\u{2022}".to_string(), "Hidden".to_string()));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_filter_posts_by_flair() {
+		let post = |id: &str, flair_id: &str, flair_text: &str| {
+			json!({"data": {
+				"id": id,
+				"subreddit": "rust",
+				"author": "spez",
+				"link_flair_template_id": flair_id,
+				"link_flair_text": flair_text,
+			}})
+		};
+		let mut posts = vec![
+			parse_post(&post("a", "1a2b-3c4d", "Meme")).await,
+			parse_post(&post("b", "5e6f-7a8b", "Discussion")).await,
+			parse_post(&post("c", "", "")).await,
+		];
+		assert_eq!(posts[0].flair_id.as_deref(), Some("1a2b-3c4d"));
+		assert_eq!(posts[2].flair_id, None);
+
+		let by_id = HashSet::from(["flair:1a2b-3c4d".to_string()]);
+		assert_eq!(filter_posts(&mut posts, &by_id), (1, false));
+		assert_eq!(posts.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["b", "c"]);
+
+		let by_text = HashSet::from(["flair:discussion".to_string()]);
+		assert_eq!(filter_posts(&mut posts, &by_text), (1, false));
+		assert_eq!(posts.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["c"]);
+	}
+
+	#[test]
+	fn test_sanitize_title() {
+		assert_eq!(sanitize_title("Free\u{200B}money"), "Freemoney");
+		assert_eq!(sanitize_title("invoice\u{202E}fdp.exe"), "invoicefdp.exe");
+		assert_eq!(sanitize_title("\u{2067}isolated\u{2069} and \u{FEFF}bom\u{0007}"), "isolated and bom");
+		assert_eq!(sanitize_title("Line\nbreak"), "Line break");
+		assert_eq!(sanitize_title("ab\u{200D}cd"), "abcd");
+
+		// Emoji, including zero-width joiner sequences, are left alone
+		assert_eq!(
+			sanitize_title("Hello 👋 from 👩\u{200D}💻 and 🏳\u{FE0F}\u{200D}🌈"),
+			"Hello 👋 from 👩\u{200D}💻 and 🏳\u{FE0F}\u{200D}🌈"
+		);
+		assert_eq!(sanitize_title("Ünïcödé タイトル"), "Ünïcödé タイトル");
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_share_url() {
+		let post = parse_post(&json!({"data": {
+			"id": "abc123",
+			"subreddit": "rust",
+			"permalink": "/r/rust/comments/abc123/hello_world/?utm_source=share&utm_medium=web2x",
+		}}))
+		.await;
+		let expected = "https://redlib.example.com/r/rust/comments/abc123/hello_world/";
+		assert_eq!(post.share_url("redlib.example.com"), expected);
+		assert_eq!(post.share_url("redlib.example.com/"), expected);
+		assert_eq!(post.share_url("https://redlib.example.com//"), expected);
+		assert_eq!(post.share_url("http://localhost:8080"), "http://localhost:8080/r/rust/comments/abc123/hello_world/");
+
+		assert_eq!(post.short_share_url("redlib.example.com/"), "https://redlib.example.com/abc123");
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_total_awards() {
+		let awardings = json!([{"name": "Helpful", "count": 2}, {"name": "Wholesome", "count": 3}]);
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "all_awardings": awardings, "total_awards_received": 7}})).await;
+		assert_eq!(post.total_awards, 7);
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "all_awardings": awardings}})).await;
+		assert_eq!(post.total_awards, 5);
+
+		let post = parse_post(&json!({"data": {"id": "abc123"}})).await;
+		assert_eq!(post.total_awards, 0);
+	}
+
+	#[test]
+	fn test_awards_total_coins() {
+		let awards = Awards::parse(
+			&json!([
+				{"name": "Helpful", "count": 2, "coin_price": 150},
+				{"name": "Gold", "count": 3, "coin_price": 500},
+				{"name": "Free award", "count": 4},
+			]),
+			&json!({}),
+		);
+		assert_eq!(awards.total_coins(), 1800);
+		assert_eq!(awards.coins_spent(), ("1.8k".to_string(), "1800".to_string()));
+
+		assert_eq!(Awards::parse(&json!([]), &json!({"gid_2": 1})).total_coins(), 0);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_crosspost_parent() {
+		let post = parse_post(&json!({"data": {
+			"id": "abc123",
+			"title": "Look at this",
+			"crosspost_parent_list": [{
+				"id": "def456",
+				"title": "Original <title>",
+				"subreddit": "pics",
+				"author": "ferris",
+				"permalink": "/r/pics/comments/def456/original_title/",
+				"post_hint": "image",
+				"domain": "i.redd.it",
+				"url": "https://i.redd.it/def456.jpg",
+				"crosspost_parent_list": [{"id": "ghi789", "title": "Older original"}],
+			}],
+		}}))
+		.await;
+		let parent = post.crosspost_parent.expect("crosspost parent");
+		assert_eq!(parent.id, "def456");
+		assert_eq!(parent.title, "Original <title>");
+		assert_eq!(parent.community, "pics");
+		assert_eq!(parent.author, "ferris");
+		assert_eq!(parent.permalink, "/r/pics/comments/def456/original_title/");
+		assert_eq!(parent.post_type, PostType::Image);
+		assert_eq!(parent.media.url, "/img/def456.jpg");
+		assert_eq!(parent.link(), "/r/pics/comments/def456/original_title/");
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "crosspost_parent_list": [{"id": "def456", "title": "Original"}]}})).await;
+		assert_eq!(post.crosspost_parent.expect("crosspost parent").link(), "/comments/def456");
+
+		let post = parse_post(&json!({"data": {"id": "abc123"}})).await;
+		assert!(post.crosspost_parent.is_none());
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_filter_blocked_authors() {
+		let post = |id: &str, author: &str| json!({"data": {"id": id, "subreddit": "rust", "author": author}});
+		let mut posts = vec![
+			parse_post(&post("a", "spez")).await,
+			parse_post(&post("b", "ferris")).await,
+			parse_post(&post("c", "spez")).await,
+		];
+
+		// A blocked user name doesn't act as a subreddit or `u_` filter, and vice versa
+		assert_eq!(filter_posts(&mut posts, &HashSet::from(["spez".to_string()])), (0, false));
+		assert_eq!(filter_blocked_authors(&mut posts, &HashSet::from(["u_spez".to_string()])), (0, false));
+
+		assert_eq!(filter_blocked_authors(&mut posts, &HashSet::from(["spez".to_string()])), (2, false));
+		assert_eq!(posts.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["b"]);
+		assert_eq!(filter_blocked_authors(&mut posts, &HashSet::from(["ferris".to_string()])), (1, true));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_filter_low_score_posts() {
+		let post = |id: &str, score: i64, hidden: bool| json!({"data": {"id": id, "score": score, "hide_score": hidden}});
+		let mut posts = vec![
+			parse_post(&post("below", 9, false)).await,
+			parse_post(&post("at", 10, false)).await,
+			parse_post(&post("above", 11, false)).await,
+			parse_post(&post("hidden", 0, true)).await,
+		];
+		assert_eq!(filter_low_score_posts(&mut posts, 10), (1, false));
+		assert_eq!(posts.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["at", "above", "hidden"]);
+
+		let mut posts = vec![parse_post(&post("below", 9, false)).await];
+		assert_eq!(filter_low_score_posts(&mut posts, 10), (1, true));
+		assert_eq!(filter_low_score_posts(&mut posts, 10), (0, false));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_filter_promoted_posts() {
+		let mut posts = vec![
+			parse_post(&json!({"data": {"id": "ad", "subreddit": "u_brand", "promoted": true}})).await,
+			parse_post(&json!({"data": {"id": "ads_ui", "subreddit": "rust", "is_created_from_ads_ui": true}})).await,
+			parse_post(&json!({"data": {"id": "organic", "subreddit": "rust"}})).await,
+		];
+		assert!(posts[0].promoted && posts[1].promoted);
+		assert!(!posts[2].promoted);
+
+		filter_promoted_posts(&mut posts);
+		assert_eq!(posts.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["organic"]);
+		assert_eq!(filter_posts(&mut posts, &HashSet::from(["u_brand".to_string()])), (0, false));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_filter_posts_by_domain() {
+		let mut posts = vec![
+			parse_post(&json!({"data": {"id": "exact", "domain": "nytimes.com"}})).await,
+			parse_post(&json!({"data": {"id": "subdomain", "domain": "cooking.NYTimes.com"}})).await,
+			parse_post(&json!({"data": {"id": "lookalike", "domain": "notnytimes.com"}})).await,
+			parse_post(&json!({"data": {"id": "other", "domain": "example.org"}})).await,
+		];
+		let domains = HashSet::from(["nytimes.com".to_string()]);
+
+		assert_eq!(filter_posts_by_domain(&mut posts, &domains), (2, false));
+		assert_eq!(posts.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["lookalike", "other"]);
+		assert_eq!(
+			filter_posts_by_domain(&mut posts, &HashSet::from(["notnytimes.com".to_string(), "example.org".to_string()])),
+			(2, true)
+		);
+		assert_eq!(filter_posts_by_domain(&mut posts, &domains), (0, false));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_filter_stickied_posts() {
+		let mut posts = vec![
+			parse_post(&json!({"data": {"id": "rules", "subreddit": "rust", "stickied": true}})).await,
+			parse_post(&json!({"data": {"id": "first", "subreddit": "rust"}})).await,
+			parse_post(&json!({"data": {"id": "weekly", "subreddit": "rust", "stickied": true}})).await,
+			parse_post(&json!({"data": {"id": "second", "subreddit": "rust", "stickied": false}})).await,
+		];
+
+		assert_eq!(filter_stickied_posts(&mut posts), 2);
+		assert_eq!(posts.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["first", "second"]);
+		assert_eq!(filter_stickied_posts(&mut posts), 0);
+		assert_eq!(filter_stickied_posts(&mut Vec::new()), 0);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_title_truncated() {
+		let truncate = |title: &str, max: usize| {
+			let data = json!({"data": {"id": "abc123", "title": title}});
+			async move { parse_post(&data).await.title_truncated(max) }
+		};
+
+		let long = "Rust 1.80 released with LazyCell and exclusive ranges";
+		assert_eq!(truncate(long, 0).await, long);
+		assert_eq!(truncate(long, 100).await, long);
+		assert_eq!(truncate(long, 20).await, "Rust 1.80 released…");
+		assert_eq!(truncate(long, 18).await, "Rust 1.80 released…");
+		assert_eq!(truncate(long, 3).await, "Rus…");
+		assert_eq!(truncate("Ask me anything", 6).await, "Ask me…");
+		assert_eq!(truncate("Tom &amp; Jerry", 6).await, "Tom…");
+		assert_eq!(truncate("R&amp;D", 4).await, "R…");
+		assert_eq!(truncate("héllo wörld", 8).await, "héllo…");
+	}
+
+	#[test]
+	fn test_author_color() {
+		assert_eq!(author_color("spez"), author_color("spez"));
+		assert_eq!(author_color("spez"), author_color("Spez"));
+		assert_ne!(author_color("spez"), author_color("kn0thing"));
+		assert!(author_color("spez").starts_with("hsl("));
+		assert_eq!(author_color("[deleted]"), "hsl(0, 0%, 55%)");
+		assert_eq!(author_color("AutoModerator"), "hsl(0, 0%, 45%)");
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_community_display() {
+		let post = parse_post(&json!({"data": {"id": "abc123", "subreddit": "rust"}})).await;
+		assert_eq!(post.community_display(), "r/rust");
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "subreddit": "u_spez"}})).await;
+		assert_eq!(post.community_display(), "u/spez");
+	}
+
+	#[test]
+	fn test_normalize_subreddit() {
+		assert_eq!(normalize_subreddit("Rust").as_deref(), Some("Rust"));
+		assert_eq!(normalize_subreddit("r/rust").as_deref(), Some("rust"));
+		assert_eq!(normalize_subreddit("/r/Rust/").as_deref(), Some("Rust"));
+		assert_eq!(normalize_subreddit(" /r/linux_gaming ").as_deref(), Some("linux_gaming"));
+		assert_eq!(normalize_subreddit("cpp").as_deref(), Some("cpp"));
+		assert_eq!(normalize_subreddit("de"), None);
+		assert_eq!(normalize_subreddit("r/"), None);
+		assert_eq!(normalize_subreddit("x"), None);
+		assert_eq!(normalize_subreddit("rust-lang"), None);
+		assert_eq!(normalize_subreddit("../settings"), None);
+		assert_eq!(normalize_subreddit("abcdefghijklmnopqrstuv"), None);
+	}
+
+	#[test]
+	fn test_cached_themes() {
+		assert_eq!(*THEMES, theme_names());
+		assert_eq!(THEMES[0], "system");
+		assert!(THEMES.contains(&"dark".to_string()));
+		assert!(THEMES[1..].windows(2).all(|pair| pair[0] <= pair[1]));
+	}
+
+	#[test]
+	fn test_poll_end_phrasing() {
+		let poll = |end: Option<Duration>| {
+			let end = end.map(|offset| ((OffsetDateTime::now_utc() + offset).unix_timestamp() * 1000) as f64);
+			Poll::parse(&json!({"total_vote_count": 12, "voting_end_timestamp": end, "options": []})).unwrap()
+		};
+
+		let ended = poll(Some(Duration::days(-2) - Duration::minutes(1)));
+		assert!(ended.is_closed());
+		assert_eq!(ended.end_phrasing(), "Poll ended 2d ago");
+
+		let open = poll(Some(Duration::hours(3) + Duration::minutes(1)));
+		assert!(!open.is_closed());
+		assert_eq!(open.end_phrasing(), "Poll closes in 3h");
+
+		let untimed = poll(None);
+		assert!(!untimed.is_closed());
+		assert_eq!(untimed.end_phrasing(), "Poll open");
+	}
+
+	#[test]
+	fn test_poll_option_percentages() {
+		let poll = Poll::parse(&json!({
+			"total_vote_count": 3,
+			"options": [
+				{"id": "1", "text": "Yes", "vote_count": 2},
+				{"id": "2", "text": "No", "vote_count": 1},
+				{"id": "3", "text": "Maybe"},
+			],
+		}))
+		.unwrap();
+		assert_eq!(poll.option_percentages(), [Some(67), Some(33), None]);
+
+		let unvoted = Poll::parse(&json!({
+			"total_vote_count": 0,
+			"options": [
+				{"id": "1", "text": "Yes", "vote_count": 0},
+				{"id": "2", "text": "No", "vote_count": 0},
+			],
+		}))
+		.unwrap();
+		assert_eq!(unvoted.option_percentages(), [Some(0), Some(0)]);
+	}
+
+	#[test]
+	fn test_parse_prediction() {
+		let prediction = Poll::parse(&json!({
+			"is_prediction": true,
+			"prediction_status": "resolved",
+			"resolved_option_id": "2",
+			"tournament_id": "abc",
+			"voting_end_timestamp": 1_700_000_000_000_u64,
+			"options": [
+				{"id": "1", "text": "Home win", "vote_count": 40, "total_stake": 1200},
+				{"id": "2", "text": "Away win", "vote_count": 60, "total_stake": 3400},
+			],
+		}))
+		.unwrap();
+		let status = prediction.prediction.as_ref().map(|p| p.status.as_str());
+		assert_eq!(status, Some("resolved"));
+		assert_eq!(prediction.total_vote_count, 0);
+		assert_eq!(prediction.poll_options.len(), 2);
+		assert!(!prediction.is_resolved_option(&prediction.poll_options[0]));
+		assert!(prediction.is_resolved_option(&prediction.poll_options[1]));
+
+		let poll = Poll::parse(&json!({"total_vote_count": 12, "options": [{"id": "1", "text": "Yes"}]})).unwrap();
+		assert!(poll.prediction.is_none());
+		assert!(!poll.is_resolved_option(&poll.poll_options[0]));
+
+		// Plain polls still need a vote count
+		assert!(Poll::parse(&json!({"options": []})).is_none());
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_layout_hint() {
+		let post = parse_post(&json!({"data": {
+			"id": "abc123",
+			"is_gallery": true,
+			"gallery_data": {"items": [{"media_id": "one"}, {"media_id": "two"}]},
+		}}))
+		.await;
+		assert_eq!(post.layout_hint.as_deref(), Some("gallery"));
+		assert!(!post.is_grid_gallery());
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "is_gallery": true, "gallery_data": {"items": [{"media_id": "one"}]}}})).await;
+		assert_eq!(post.layout_hint.as_deref(), Some("single"));
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "is_gallery": true, "view_type": "grid"}})).await;
+		assert_eq!(post.layout_hint.as_deref(), Some("grid"));
+		assert!(post.is_grid_gallery());
+
+		let post = parse_post(&json!({"data": {"id": "abc123"}})).await;
+		assert_eq!(post.layout_hint, None);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_num_crossposts() {
+		let post = parse_post(&json!({"data": {"id": "abc123", "num_crossposts": 1532}})).await;
+		assert_eq!(post.num_crossposts, 1532);
+		assert_eq!(post.crossposts(), ("1.5k".to_string(), "1532".to_string()));
+
+		let post = parse_post(&json!({"data": {"id": "abc123"}})).await;
+		assert_eq!(post.num_crossposts, 0);
+	}
+
+	#[test]
+	fn test_constrain_images() {
+		assert_eq!(
+			constrain_images(r#"<p><img src="/img/abc.png" width="4000" height="3000"></p>"#),
+			r#"<p><img class="constrained" src="/img/abc.png" width="4000" height="3000"></p>"#
+		);
+		assert_eq!(
+			constrain_images(r#"<img class="giphy" src="/preview/external-pre/abc.gif">"#),
+			r#"<img class="constrained giphy" src="/preview/external-pre/abc.gif">"#
+		);
+		let emoji = r#"<img src="/preview/pre/emote.png" width="20" height="20">"#;
+		assert_eq!(constrain_images(emoji), emoji);
+		let external = r#"<img src="https://example.com/abc.png">"#;
+		assert_eq!(constrain_images(external), external);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_media_parse_post_types() {
+		for (data, post_type) in [
+			(
+				json!({"preview": {"reddit_video_preview": {"fallback_url": "https://v.redd.it/abc/DASH_720.mp4", "is_gif": false}}}),
+				PostType::Video,
+			),
+			(
+				json!({"secure_media": {"reddit_video": {"fallback_url": "https://v.redd.it/abc/DASH_720.mp4", "is_gif": true}}}),
+				PostType::Gif,
+			),
+			(json!({"post_hint": "image", "domain": "i.redd.it", "url": "https://i.redd.it/abc.png"}), PostType::Image),
+			(
+				json!({"post_hint": "image", "preview": {"images": [{"variants": {"mp4": {"source": {"url": "https://preview.redd.it/abc.gif?format=mp4"}}}}]}}),
+				PostType::Gif,
+			),
+			(json!({"is_self": true, "permalink": "/r/rust/comments/abc/x/"}), PostType::SelfText),
+			(json!({"is_gallery": true, "url": "https://www.reddit.com/gallery/abc"}), PostType::Gallery),
+			(json!({"url": "https://example.com/article"}), PostType::Link),
+		] {
+			assert_eq!(Media::parse(&data).await.0, post_type, "{data}");
+		}
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_media_parse_direct_video() {
+		let (post_type, media, _) = Media::parse(&json!({"post_hint": "image", "domain": "i.redd.it", "url": "https://i.redd.it/foo.mp4"})).await;
+		assert_eq!(post_type, PostType::Video);
+		assert_eq!(media.url, "/img/foo.mp4");
+
+		let (post_type, media, _) = Media::parse(&json!({"domain": "i.redd.it", "url": "https://i.redd.it/bar.GIFV?s=1"})).await;
+		assert_eq!(post_type, PostType::Gif);
+		assert_eq!(media.url, "/img/bar.GIFV?s=1");
+
+		let (post_type, _, _) = Media::parse(&json!({"domain": "i.redd.it", "url": "https://i.redd.it/foo.mp4.jpg", "is_reddit_media_domain": true})).await;
+		assert_eq!(post_type, PostType::Image);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_spoiler() {
+		let prefs = Preferences::default();
+		let spoiler = parse_post(&json!({"data": {"id": "abc123", "spoiler": true}})).await;
+		assert!(spoiler.spoiler);
+		assert!(spoiler.should_blur(&prefs));
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "over_18": true}})).await;
+		assert!(!post.spoiler);
+		assert!(!post.should_blur(&prefs));
+		let blur_nsfw = Preferences {
+			blur_nsfw: "on".to_string(),
+			..Preferences::default()
+		};
+		assert!(post.should_blur(&blur_nsfw));
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_link_flair_emotes() {
+		let post = parse_post(&json!({"data": {
+			"id": "abc123",
+			"link_flair_type": "richtext",
+			"link_flair_richtext": [
+				{"e": "emoji", "a": ":rustacean:", "u": "https://emoji.redditmedia.com/abc_t5_2qh1i/rustacean"},
+				{"e": "emoji", "a": ":ferris:", "u": "https://reddit-econ-prod-assets-permanent.s3.amazonaws.com/asset-manager/t5_2qh1i/ferris.png"},
+				{"e": "text", "t": " Discussion"},
+			],
+			"link_flair_text": ":rustacean::ferris: Discussion",
+		}}))
+		.await;
+
+		let parts = post
+			.flair
+			.flair_parts
+			.iter()
+			.map(|part| (part.flair_part_type.as_str(), part.value.as_str()))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			parts,
+			[
+				("emoji", "/emoji/abc_t5_2qh1i/rustacean"),
+				("emoji", "/emote/asset-manager/t5_2qh1i/ferris.png"),
+				("text", " Discussion")
+			]
+		);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_parse_flair_css_class() {
+		let post = parse_post(&json!({"data": {"id": "abc123", "link_flair_css_class": "discussion mod-post"}})).await;
+		assert_eq!(post.flair.css_class, "discussion mod-post");
+
+		let post = parse_post(&json!({"data": {"id": "abc123"}})).await;
+		assert_eq!(post.flair.css_class, "");
+
+		assert_eq!(sanitize_class_names("news\" onmouseover=\"alert(1) <b>"), "news onmouseoveralert1 b");
+		assert_eq!(sanitize_class_names("\u{e9}t\u{e9} ;{}"), "t");
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_media_parse_quarantined_crosspost() {
+		let (post_type, media, _) = Media::parse(&json!({
+			"crosspost_parent_list": [{
+				"quarantine": true,
+				"secure_media": {"reddit_video": {"fallback_url": "https://v.redd.it/abc/DASH_720.mp4?source=fallback", "is_gif": false}},
+			}],
+		}))
+		.await;
+		assert_eq!(post_type, PostType::Video);
+		assert!(media.needs_quarantine_ack);
+
+		let (_, media, _) = Media::parse(&json!({"crosspost_parent_list": [{"quarantine": false}]})).await;
+		assert!(!media.needs_quarantine_ack);
+
+		let (_, media, _) = Media::parse(&json!({"url": "https://example.com"})).await;
+		assert!(!media.needs_quarantine_ack);
+	}
+
+	#[test]
+	fn test_time_display() {
+		let (rel_time, created) = ("2d ago", "Jan 02 2024, 03:04:05 UTC");
+		assert_eq!(time_display(rel_time, created, "relative"), (rel_time, created));
+		assert_eq!(time_display(rel_time, created, "absolute"), (created, rel_time));
+		assert_eq!(time_display(rel_time, created, ""), (rel_time, created));
+	}
+
+	#[test]
+	fn test_param() {
+		assert_eq!(param("/r/rust/search?q=tokio&restrict_sr=on", "q"), Some("tokio".to_string()));
+		assert_eq!(param("r/rust/top.json?t=week", "t"), Some("week".to_string()));
+		assert_eq!(param("/r/rust", "q"), None);
+	}
+
+	#[test]
+	fn test_gallery_media_status() {
+		let items = json!([{"media_id": "ok"}, {"media_id": "broken"}, {"media_id": "pending"}, {"media_id": "legacy"}]);
+		let metadata = json!({
+			"ok": {"status": "valid", "m": "image/jpg", "s": {"u": "https://preview.redd.it/ok.jpg?width=640&s=abc", "x": 640, "y": 480}},
+			"broken": {"status": "failed"},
+			"pending": {"status": "unprocessed"},
+			"legacy": {"m": "image/png", "s": {"u": "https://preview.redd.it/legacy.png?width=640&s=def", "x": 640, "y": 480}},
+		});
+
+		let gallery = GalleryMedia::parse(&items, &metadata);
+		assert_eq!(
+			gallery.iter().map(|image| (image.url.as_str(), image.processing)).collect::<Vec<_>>(),
+			[
+				("/preview/pre/ok.jpg?width=640&s=abc", false),
+				("", true),
+				("/preview/pre/legacy.png?width=640&s=def", false),
+			]
+		);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_media_parse_image_with_loop_preview() {
+		let (post_type, media, _) = Media::parse(&json!({
+			"post_hint": "image",
+			"domain": "i.redd.it",
+			"url": "https://i.redd.it/abc.jpg",
+			"preview": {"reddit_video_preview": {"fallback_url": "https://v.redd.it/abc/DASH_480.mp4?source=fallback", "is_gif": true}},
+		}))
+		.await;
+		assert_eq!(post_type, PostType::Image);
+		assert_eq!(media.url, "/img/abc.jpg");
+		assert_eq!(media.loop_preview_url.as_deref(), Some("/vid/abc/480.mp4"));
+
+		let (_, media, _) = Media::parse(&json!({"post_hint": "image", "domain": "i.redd.it", "url": "https://i.redd.it/abc.jpg"})).await;
+		assert_eq!(media.loop_preview_url, None);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_media_parse_removed_media() {
+		let (post_type, media, _) = Media::parse(&json!({
+			"post_hint": "image",
+			"domain": "i.imgur.com",
+			"url": "https://i.imgur.com/removed.png",
+			"preview": {"images": [{"source": {"url": "https://external-preview.redd.it/abc.png", "width": 161, "height": 81}}]},
+		}))
+		.await;
+		assert_eq!(post_type, PostType::RemovedMedia);
+		assert!(media.url.is_empty());
+		assert!(media.poster.is_empty());
+
+		let (post_type, _, gallery) = Media::parse(&json!({
+			"is_gallery": true,
+			"url": "https://www.reddit.com/gallery/abc123",
+			"gallery_data": {"items": [{"media_id": "one"}]},
+			"media_metadata": {"one": {"status": "failed"}},
+		}))
+		.await;
+		assert_eq!(post_type, PostType::RemovedMedia);
+		assert!(gallery.is_empty());
+
+		let (post_type, _, _) = Media::parse(&json!({"post_hint": "image", "domain": "i.redd.it", "url": "https://i.redd.it/removed_cat.png"})).await;
+		assert_eq!(post_type, PostType::Image);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_single_item_gallery() {
+		let metadata = json!({
+			"one": {"status": "valid", "m": "image/jpg", "s": {"u": "https://preview.redd.it/one.jpg?width=640&s=abc", "x": 640, "y": 480}},
+			"two": {"status": "valid", "m": "image/jpg", "s": {"u": "https://preview.redd.it/two.jpg?width=640&s=def", "x": 640, "y": 480}},
+		});
+
+		let post = parse_post(&json!({"data": {
+			"id": "abc123",
+			"is_gallery": true,
+			"url": "https://www.reddit.com/gallery/abc123",
+			"gallery_data": {"items": [{"media_id": "one", "caption": "The only one"}]},
+			"media_metadata": metadata,
+		}}))
+		.await;
+		assert_eq!(post.post_type, PostType::Image);
+		assert_eq!(post.media.url, "/preview/pre/one.jpg?width=640&s=abc");
+		assert_eq!((post.media.width, post.media.height), (640, 480));
+		assert_eq!(post.gallery.len(), 1);
+		assert_eq!(post.gallery[0].caption, "The only one");
+
+		let post = parse_post(&json!({"data": {
+			"id": "abc123",
+			"is_gallery": true,
+			"url": "https://www.reddit.com/gallery/abc123",
+			"gallery_data": {"items": [{"media_id": "one"}, {"media_id": "two"}]},
+			"media_metadata": metadata,
+		}}))
+		.await;
+		assert_eq!(post.post_type, PostType::Gallery);
+		assert_eq!(post.gallery.len(), 2);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_has_renderable_media() {
+		let image = parse_post(&json!({"data": {"id": "abc123", "post_hint": "image", "domain": "i.redd.it", "url": "https://i.redd.it/abc.jpg"}})).await;
+		assert_eq!(image.post_type, PostType::Image);
+		assert!(image.has_renderable_media());
+
+		let broken = parse_post(&json!({"data": {"id": "abc123", "post_hint": "image", "domain": "imgur.com", "url": "https://imgur.com/abc"}})).await;
+		assert_eq!(broken.post_type, PostType::Image);
+		assert!(broken.media.url.is_empty());
+		assert!(!broken.has_renderable_media());
+
+		let text = parse_post(&json!({"data": {"id": "abc123", "is_self": true, "permalink": "/r/rust/comments/abc123/x/"}})).await;
+		assert!(!text.has_renderable_media());
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_resolve_media_keeps_order() {
+		let post_list = (0..20)
+			.map(|i| json!({"kind": "t3", "data": {"id": format!("post{i}"), "post_hint": "image", "domain": "i.redd.it", "url": format!("https://i.redd.it/{i}.jpg")}}))
+			.collect::<Vec<_>>();
+
+		let resolved = resolve_media(&post_list).await;
+		assert_eq!(resolved.len(), post_list.len());
+		for (i, ((post_type, media, _), crosspost_parent)) in resolved.iter().enumerate() {
+			assert_eq!(*post_type, PostType::Image);
+			assert_eq!(media.url, format!("/img/{i}.jpg"));
+			assert!(crosspost_parent.is_none());
+		}
+		assert!(resolve_media(&[]).await.is_empty());
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_selftext_link_preview() {
+		let preview = json!({"images": [{"source": {"url": "https://external-preview.redd.it/abc123.jpg?width=640&s=def", "width": 640, "height": 360}}]});
+		let post = parse_post(&json!({"data": {
+			"id": "abc123",
+			"is_self": true,
+			"permalink": "/r/rust/comments/abc123/x/",
+			"selftext_html": "<div class=\"md\"><p><a href=\"https://blog.rust-lang.org/\">Rust blog</a></p></div>",
+			"preview": preview,
+		}}))
+		.await;
+		assert_eq!(post.post_type, PostType::SelfText);
+		assert!(post.body.contains("Rust blog"));
+		let link_preview = post.link_preview.unwrap();
+		assert_eq!(link_preview.url, "/preview/external-pre/abc123.jpg?width=640&s=def");
+		assert_eq!((link_preview.width, link_preview.height), (640, 360));
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "is_self": true}})).await;
+		assert!(post.link_preview.is_none());
+
+		let post = parse_post(&json!({"data": {"id": "abc123", "url": "https://blog.rust-lang.org/", "preview": preview}})).await;
+		assert!(post.link_preview.is_none());
+	}
+
+	#[test]
+	fn test_post_type_round_trip() {
+		for post_type in [
+			PostType::Image,
+			PostType::Gif,
+			PostType::Video,
+			PostType::Gallery,
+			PostType::Link,
+			PostType::SelfText,
+			PostType::Live,
+			PostType::RemovedMedia,
+		] {
+			assert_eq!(post_type.as_str().parse::<PostType>(), Ok(post_type));
+			assert_eq!(post_type.to_string(), post_type.as_str());
+		}
+		assert!("selfie".parse::<PostType>().is_err());
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_post_type_helpers() {
+		let mut post = parse_post(&json!({"data": {"id": "abc123"}})).await;
+		for (post_type, is_self, is_media) in [
+			("self", true, false),
+			("image", false, true),
+			("video", false, true),
+			("gif", false, true),
+			("gallery", false, true),
+			("link", false, false),
+		] {
+			post.post_type = post_type.parse().unwrap();
+			assert_eq!(post.is_self(), is_self, "{post_type}");
+			assert_eq!(post.is_media(), is_media, "{post_type}");
+		}
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_comments_url() {
+		let link = parse_post(&json!({"data": {
+			"id": "abc123",
+			"subreddit": "rust",
+			"permalink": "/r/rust/comments/abc123/some_link/",
+			"url": "https://example.com/article",
+		}}))
+		.await;
+		assert_eq!(link.post_type, PostType::Link);
+		assert_eq!(link.comments_url(), "/r/rust/comments/abc123");
+
+		let text = parse_post(&json!({"data": {
+			"id": "def456",
+			"subreddit": "rust",
+			"permalink": "/r/rust/comments/def456/some_text/",
+			"is_self": true,
+		}}))
+		.await;
+		assert_eq!(text.post_type, PostType::SelfText);
+		assert_eq!(text.comments_url(), "/r/rust/comments/def456");
+	}
+
+	#[test]
+	fn test_awards_fall_back_to_gildings() {
+		let awards = Awards::parse(&json!([]), &json!({"gid_1": 2, "gid_2": 0, "gid_3": 1}));
+		assert_eq!(
+			awards.iter().map(|award| (award.name.as_str(), award.count)).collect::<Vec<_>>(),
+			[("Silver", 2), ("Platinum", 1)]
+		);
+		assert_eq!(awards[0].icon_url, "/static/gold/awards/icon/silver_64.png");
+
+		let awardings = json!([{"name": "Helpful", "count": 3, "description": "", "resized_icons": []}]);
+		let awards = Awards::parse(&awardings, &json!({"gid_2": 5}));
+		assert_eq!(awards.len(), 1);
+		assert_eq!(awards[0].name, "Helpful");
+
+		// Outside SFW-only mode, NSFW awards are kept
+		let awards = Awards::parse(&json!([{"name": "Spicy", "count": 1, "is_nsfw": true}]), &json!({}));
+		assert_eq!(awards.len(), 1);
+	}
+
+	#[test]
+	fn test_debug_flags_overlay_preferences() {
+		let req = Request::builder()
+			.uri("/r/rust?redlib_raw=1&redlib_show_nsfw=1&redlib_bogus=on")
+			.body(Body::empty())
+			.unwrap();
+		let prefs = Preferences::new(&req);
+		assert_eq!(prefs.show_raw_markdown, "on");
+		assert_eq!(prefs.show_nsfw, "");
+
+		let req = Request::builder()
+			.uri("/r/rust?redlib_raw=0")
+			.header("Cookie", "show_raw_markdown=on")
+			.body(Body::empty())
+			.unwrap();
+		assert_eq!(Preferences::new(&req).show_raw_markdown, "off");
+	}
 }
 
 #[test]
@@ -1126,6 +3794,13 @@ fn test_rewriting_emoji() {
 	assert_eq!(rewrite_urls(input), output);
 }
 
+#[test]
+fn test_rewriting_shortlinks() {
+	let input = r#"<div class="md"><p>See <a href="https://redd.it/1abc2de">this post</a> and <a href="http://redd.it/xyz789/">that one</a>, not <a href="https://i.redd.it/abc123.png">an image</a>.</p></div>"#;
+	let output = r#"<div class="md"><p>See <a href="/comments/1abc2de">this post</a> and <a href="/comments/xyz789">that one</a>, not <a href="https://i.redd.it/abc123.png">an image</a>.</p></div>"#;
+	assert_eq!(rewrite_urls_tracked(input), (output.to_string(), vec!["shortlink"]));
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_fetching_subreddit_quarantined() {
 	let subreddit = Post::fetch("/r/drugs", true).await;
@@ -1148,3 +3823,54 @@ async fn test_fetching_ws() {
 		assert!(post.ws_url.starts_with("wss://k8s-lb.wss.redditmedia.com/link/"));
 	}
 }
+
+#[cfg(test)]
+use sealed_test::prelude::*;
+
+#[test]
+fn test_nsfw_bypass_token() {
+	let now = OffsetDateTime::now_utc().unix_timestamp();
+	let token = nsfw_bypass_token(now + 60);
+	let req = Request::builder().header("Cookie", format!("nsfw_bypass={token}")).body(Body::empty()).unwrap();
+	assert!(is_valid_nsfw_bypass_token(&token, now));
+	assert!(!should_be_nsfw_gated(&req, "/r/rust/comments/abc123/x/"));
+
+	// Expired or tampered tokens don't bypass the gate
+	let expired = nsfw_bypass_token(now - 60);
+	let req = Request::builder().header("Cookie", format!("nsfw_bypass={expired}")).body(Body::empty()).unwrap();
+	assert!(!is_valid_nsfw_bypass_token(&expired, now));
+	assert!(should_be_nsfw_gated(&req, "/r/rust/comments/abc123/x/"));
+
+	let (_, tag) = token.split_once('.').unwrap();
+	assert!(!is_valid_nsfw_bypass_token(&format!("{}.{tag}", now + 3600), now));
+	assert!(!is_valid_nsfw_bypass_token("garbage", now));
+}
+
+#[test]
+fn test_nsfw_gate_without_bypass() {
+	let req = Request::builder().body(Body::empty()).unwrap();
+	assert!(should_be_nsfw_gated(&req, "/r/rust/comments/abc123/x/"));
+
+	// show_nsfw stays the persistent opt-in
+	let req = Request::builder().header("Cookie", "show_nsfw=on").body(Body::empty()).unwrap();
+	assert!(!should_be_nsfw_gated(&req, "/r/rust/comments/abc123/x/"));
+}
+
+#[test]
+#[sealed_test(env = [("REDLIB_SFW_ONLY", "on")])]
+fn test_nsfw_bypass_token_sfw_only() {
+	let token = nsfw_bypass_token(OffsetDateTime::now_utc().unix_timestamp() + 60);
+	let req = Request::builder().header("Cookie", format!("nsfw_bypass={token}")).body(Body::empty()).unwrap();
+	assert!(should_be_nsfw_gated(&req, "/r/rust/comments/abc123/x/&bypass_nsfw_landing"));
+}
+
+#[test]
+#[sealed_test(env = [("REDLIB_SFW_ONLY", "on")])]
+fn test_awards_sfw_only() {
+	let items = serde_json::json!([
+		{"name": "Wholesome", "count": 2, "description": "", "resized_icons": []},
+		{"name": "Spicy", "count": 1, "description": "", "resized_icons": [], "is_nsfw": true},
+	]);
+	let awards = Awards::parse(&items, &Value::Null);
+	assert_eq!(awards.iter().map(|award| award.name.as_str()).collect::<Vec<_>>(), ["Wholesome"]);
+}